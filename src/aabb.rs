@@ -1,6 +1,6 @@
-use std::simd::{cmp::SimdPartialOrd, LaneCount, Mask, SupportedLaneCount};
+use std::simd::{cmp::SimdPartialOrd, num::SimdFloat, LaneCount, Mask, SupportedLaneCount};
 
-use crate::{interval::{Interval, SimdInterval, SimdIntervalMask}, ray::{Ray, SimdRay}, simd::{MaskedSimd, SimdVec}, sieve::SimdSieve, util::simd_of, vector::Point3};
+use crate::{interval::{Interval, SimdInterval, SimdIntervalMask}, ray::{Ray, SimdRay}, simd::MaskedSimd, util::simd_of, vector::{Point3, SimdPoint3, SimdVector3, Vector3}};
 
 #[derive(Clone, Copy, Debug)]
 pub struct AABB {
@@ -52,6 +52,16 @@ impl AABB {
         }
     }
 
+    // Makes sure no side is thinner than delta, so flat shapes can still be hit
+    pub fn pad(&self, delta: f64) -> Self {
+        let pad = |interval: Interval| if interval.size() < delta {interval.expand(delta / 2.0)} else {interval};
+        AABB {
+            x: pad(self.x),
+            y: pad(self.y),
+            z: pad(self.z),
+        }
+    }
+
     pub fn test(&self, ray: &Ray, ray_t: &Interval) -> Option<Interval> {
         let mut max = ray_t.max;
         let mut min = ray_t.min;
@@ -132,7 +142,7 @@ impl AABB {
         min = select.select(min_lt, min_ge);
         max = select.select(max_lt, max_ge);
 
-        mask = mask & min.simd_gt(max);
+        mask = mask & max.simd_gt(min);
 
 
         let t0 = (simd_of(self.y.min) - rays.origins.y()) * adinv.y();
@@ -149,7 +159,7 @@ impl AABB {
         min = select.select(min_lt, min_ge);
         max = select.select(max_lt, max_ge);
 
-        mask = mask & min.simd_gt(max);
+        mask = mask & max.simd_gt(min);
 
 
         let t0 = (simd_of(self.z.min) - rays.origins.z()) * adinv.z();
@@ -166,38 +176,66 @@ impl AABB {
         min = select.select(min_lt, min_ge);
         max = select.select(max_lt, max_ge);
 
-        mask = mask & min.simd_gt(max);
+        mask = mask & max.simd_gt(min);
 
         MaskedSimd {simd: SimdInterval { mins: min, maxes: max }, mask: SimdIntervalMask {mins: mask, maxes: mask}}
     }
 
-    pub fn simd_vec_test<const N: usize>(
-        &self,
-        rays_vec: SimdVec<SimdRay<N>>,
-        ray_t: SimdVec<SimdInterval<N>>
-    ) -> SimdSieve<SimdRay<N>>
-        where LaneCount<N>: SupportedLaneCount,
-    {
-        let (rays, residue) = rays_vec.extract();
-        let (ray_ts, ts_residue) = ray_t.extract();
-        for (ray, ts) in rays.into_iter().zip(ray_ts) {
-            let mask = self.simd_test(ray, ts);
+    // simd_test for rays whose directions were inverted up front, so a packet divides once rather than at
+    // every box it meets. Only says which rays hit
+    pub fn simd_test_inv<const N: usize>(&self, origins: &SimdPoint3<N>, inv_dirs: &SimdVector3<N>, ray_t: &SimdInterval<N>) -> Mask<i64, N>
+        where LaneCount<N>: SupportedLaneCount {
+        let mut min = ray_t.mins;
+        let mut max = ray_t.maxes;
+        for (interval, origin, inv_dir) in [(self.x, origins.x(), inv_dirs.x()), (self.y, origins.y(), inv_dirs.y()), (self.z, origins.z(), inv_dirs.z())] {
+            let t0 = (simd_of(interval.min) - origin) * inv_dir;
+            let t1 = (simd_of(interval.max) - origin) * inv_dir;
+            min = min.simd_max(t0.simd_min(t1));
+            max = max.simd_min(t0.simd_max(t1));
         }
-        todo!()
+        max.simd_gt(min)
     }
 
     pub fn longest_axis(&self) -> usize {
-        if self.x.size() > self.z.size() {
+        if self.x.size() > self.y.size() {
             if self.x.size() > self.z.size() {0} else {2}
         } else {
             if self.y.size() > self.z.size() {1} else {2}
         }
     }
 
+    pub fn axis(&self, n: usize) -> Interval {
+        match n {
+            0 => self.x,
+            1 => self.y,
+            2 => self.z,
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn center(&self) -> Point3 {
+        Vector3(
+            (self.x.min + self.x.max) / 2.0,
+            (self.y.min + self.y.max) / 2.0,
+            (self.z.min + self.z.max) / 2.0,
+        )
+    }
+
+    pub fn surface_area(&self) -> f64 {
+        if !self.is_norm() {return 0.0;}
+        let (x, y, z) = (self.x.size(), self.y.size(), self.z.size());
+        2.0 * (x * y + y * z + z * x)
+    }
+
     pub fn is_norm(&self) -> bool {
         self.x.is_norm() && self.y.is_norm() && self.z.is_norm()
     }
 
+    // False for things like planes that go on forever along some axis
+    pub fn is_bounded(&self) -> bool {
+        [self.x, self.y, self.z].iter().all(|interval| interval.min.is_finite() && interval.max.is_finite())
+    }
+
     pub const EMPTY: AABB = AABB {
         x: Interval::EMPTY,
         y: Interval::EMPTY,
@@ -1,4 +1,4 @@
-use crate::{aabb::AABB, spline::{BoundedCurve, ConstantSpline, LinearSpline}, vector::Point3};
+use crate::{aabb::AABB, interval::Interval, spline::{BoundedCurve, ConstantSpline, LinearSpline}, vector::Point3};
 
 pub struct Animation {
     curve: Box<dyn BoundedCurve>,
@@ -25,6 +25,10 @@ impl Animation {
         self.curve.bound_radius(self.size)
     }
 
+    pub fn bound_interval(&self, time: Interval) -> AABB {
+        self.curve.bound_segment_radius(time, self.size)
+    }
+
     pub fn sample(&self, t: f64) -> Point3 {
         self.curve.sample(t)
     }
@@ -0,0 +1,32 @@
+use std::sync::{Arc, Mutex};
+
+use crate::{color::Color, math::lerp, sphere::Sphere, texture::Texture, vector::Vector3, writer::Debugger};
+
+pub enum Background {
+    Solid(Color),
+    Gradient {bottom: Color, top: Color},
+    Texture(Box<dyn Texture>),
+}
+
+impl Background {
+    pub fn value(&self, dir: &Vector3, debugger: Arc<Mutex<Debugger>>) -> Color {
+        match self {
+            Background::Solid(color) => *color,
+            Background::Gradient {bottom, top} => {
+                let a = 0.5 * (dir.unit().y() + 1.0);
+                lerp(bottom, top, a)
+            },
+            Background::Texture(texture) => {
+                // Wrapped around the scene like an environment map
+                let dir = dir.unit();
+                let (u, v) = Sphere::get_sphere_uv(dir);
+                texture.value(u, v, &dir, debugger)
+            },
+        }
+    }
+
+    pub const SKY: Background = Background::Gradient {
+        bottom: Color {r: 1.0, g: 1.0, b: 1.0},
+        top: Color {r: 0.5, g: 0.7, b: 1.0},
+    };
+}
@@ -0,0 +1,98 @@
+extern crate test;
+
+use std::simd::Simd;
+
+use test::{black_box, Bencher};
+
+use crate::{aabb::AABB, bvh::{BVHBuilder, BVHNode, SahOptions}, bvh4::{Bvh4, Bvh4Node}, color::Color, hit::Hittable, interval::Interval, linear_bvh::LinearBVH, material::Lambertian, ray::Ray, sphere::Sphere, vector::Vector3};
+
+const RAY_T: Interval = Interval {min: 0.001, max: f64::INFINITY};
+
+fn spheres() -> Vec<Box<dyn Hittable>> {
+    let material = Lambertian::from_const_col(Color::WHITE).to_dyn();
+    (0..20000_usize).map(|k| {
+        let (x, y, z) = ((k * 7919 % 1000) as f64 * 0.1, (k * 104729 % 997) as f64 * 0.02, (k * 15485863 % 991) as f64 * 0.1);
+        Sphere::from_const_pos(x, y, z, 0.05 + (k % 7) as f64 * 0.02, material.clone()).as_box()
+    }).collect()
+}
+
+fn rays() -> Vec<Ray> {
+    (0..1000).map(|k| {
+        let origin = Vector3(-20.0, 30.0, -20.0);
+        let target = Vector3((k * 37 % 100) as f64, (k % 20) as f64, (k * 53 % 100) as f64);
+        Ray {origin, dir: target - origin, time: 0.0}
+    }).collect()
+}
+
+// A pixel's samples for each of a row of pixels, seen from a camera looking into the spheres. The renderers
+// hand hit_all one pixel at a time, so these are the rays packets have to beat tracing one by one on
+fn pixels() -> Vec<Vec<Ray>> {
+    let origin = Vector3(50.0, 10.0, -30.0);
+    (0..32).map(|pixel| (0..64).map(|k| {
+        let target = Vector3(40.0 + pixel as f64 * 0.6 + (k % 8) as f64 * 0.0075, 8.0 + (k / 8) as f64 * 0.0075, 50.0);
+        Ray {origin, dir: target - origin, time: 0.0}
+    }).collect()).collect()
+}
+
+fn boxes() -> [AABB; 4] {
+    [0.0, 1.5, 3.0, 4.5].map(|x| AABB::from(Vector3(x, 0.0, 0.0), Vector3(x + 1.0, 1.0, 1.0)))
+}
+
+#[bench]
+fn aabb_test_four_boxes(b: &mut Bencher) {
+    let boxes = boxes();
+    let ray = Ray {origin: Vector3(-1.0, 0.5, -1.0), dir: Vector3(6.0, 0.01, 2.0), time: 0.0};
+    b.iter(|| boxes.iter().filter(|bbox| bbox.test(black_box(&ray), &RAY_T).is_some()).count());
+}
+
+#[bench]
+fn bvh4_node_test_four_boxes(b: &mut Bencher) {
+    let node = Bvh4Node::new(&boxes());
+    let ray = Ray {origin: Vector3(-1.0, 0.5, -1.0), dir: Vector3(6.0, 0.01, 2.0), time: 0.0};
+    // Traversal does this once per ray, not once per node
+    let origin = [ray.origin.x(), ray.origin.y(), ray.origin.z()].map(Simd::splat);
+    let inv_dir = [1.0 / ray.dir.x(), 1.0 / ray.dir.y(), 1.0 / ray.dir.z()].map(Simd::splat);
+    b.iter(|| node.test(black_box(&origin), &inv_dir, &RAY_T).0.to_bitmask().count_ones());
+}
+
+fn bench_hits(b: &mut Bencher, root: &dyn Hittable) {
+    let rays = rays();
+    b.iter(|| rays.iter().filter(|ray| root.hit(black_box(ray), &RAY_T).is_some()).count());
+}
+
+#[bench]
+fn bvh_node_hit(b: &mut Bencher) {
+    let root = BVHNode::with_builder(spheres(), BVHBuilder::Sah(SahOptions::DEFAULT), None);
+    bench_hits(b, &root);
+}
+
+#[bench]
+fn linear_bvh_hit(b: &mut Bencher) {
+    bench_hits(b, &LinearBVH::new(spheres(), BVHBuilder::Sah(SahOptions::DEFAULT)));
+}
+
+#[bench]
+fn bvh4_hit(b: &mut Bencher) {
+    bench_hits(b, &Bvh4::new(spheres(), BVHBuilder::Sah(SahOptions::DEFAULT)));
+}
+
+#[bench]
+fn bvh4_hit_all(b: &mut Bencher) {
+    let root = Bvh4::new(spheres(), BVHBuilder::Sah(SahOptions::DEFAULT));
+    let rays = rays();
+    b.iter(|| root.hit_all(black_box(&rays), &RAY_T).iter().filter(|hit| hit.is_some()).count());
+}
+
+#[bench]
+fn bvh4_hit_pixels(b: &mut Bencher) {
+    let root = Bvh4::new(spheres(), BVHBuilder::Sah(SahOptions::DEFAULT));
+    let pixels = pixels();
+    b.iter(|| pixels.iter().flatten().filter(|ray| root.hit(black_box(ray), &RAY_T).is_some()).count());
+}
+
+#[bench]
+fn bvh4_hit_all_pixels(b: &mut Bencher) {
+    let root = Bvh4::new(spheres(), BVHBuilder::Sah(SahOptions::DEFAULT));
+    let pixels = pixels();
+    b.iter(|| pixels.iter().map(|rays| root.hit_all(black_box(rays), &RAY_T).iter().filter(|hit| hit.is_some()).count()).sum::<usize>());
+}
@@ -1,92 +1,514 @@
-use std::sync::Arc;
+use std::{collections::HashMap, fmt, sync::{atomic::{AtomicUsize, Ordering}, mpsc::{self, Sender}, Arc}, time::{Duration, Instant}};
 
-use crate::{aabb::AABB, hit::{Hit, Hittable}, interval::Interval, ray::Ray, writer::Debugger};
+use threadpool::ThreadPool;
+
+use crate::{aabb::AABB, hit::{Hit, Hittable, HittableList}, interval::Interval, ray::Ray, writer::Debugger};
+
+// Subtrees with fewer objects than this are built on whichever thread split them off
+const PARALLEL_THRESHOLD: usize = 4096;
+
+// Refitting rebuilds the tree once its SAH cost gets this many times worse than right after the last rebuild
+pub const REBUILD_THRESHOLD: f64 = 1.5;
+
+// Flat trees traverse with a fixed size stack, so they can't be any deeper than this
+pub const STACK_SIZE: usize = 64;
+
+#[derive(Clone, Copy, Debug)]
+pub struct SahOptions {
+    pub bins: usize,
+    pub max_leaf_size: usize,
+    // Cost of visiting a node, relative to intersecting one object
+    pub cost_ratio: f64,
+}
+
+impl SahOptions {
+    pub const fn new(bins: usize, max_leaf_size: usize, cost_ratio: f64) -> Self {
+        Self { bins, max_leaf_size, cost_ratio }
+    }
+
+    pub const DEFAULT: SahOptions = SahOptions {bins: 16, max_leaf_size: 4, cost_ratio: 1.0};
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum BVHBuilder {
+    Median,
+    Sah(SahOptions),
+}
+
+impl BVHBuilder {
+    pub fn cost_ratio(&self) -> f64 {
+        match self {
+            BVHBuilder::Median => SahOptions::DEFAULT.cost_ratio,
+            BVHBuilder::Sah(options) => options.cost_ratio,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct BVHStats {
+    pub nodes: usize,
+    pub leaves: usize,
+    pub max_depth: usize,
+    pub leaf_objects: usize,
+    // Surface area sums the SAH cost is made from, weighted by object count for leaves
+    pub interior_area: f64,
+    pub leaf_area: f64,
+    pub root_area: f64,
+    pub cost_ratio: f64,
+    pub build_time: Duration,
+}
+
+impl BVHStats {
+    pub fn of(root: &dyn Hittable, cost_ratio: f64) -> Self {
+        let mut stats = BVHStats {cost_ratio, ..Default::default()};
+        root.bvh_stats(0, &mut stats);
+        stats
+    }
+
+    // Expected cost of a random ray through the tree, in object intersections
+    pub fn sah_cost(&self) -> f64 {
+        if self.root_area <= 0.0 {return 0.0;}
+        (self.cost_ratio * self.interior_area + self.leaf_area) / self.root_area
+    }
+}
+
+impl fmt::Display for BVHStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} nodes, {} leaves, depth {}, {:.2} objects per leaf, SAH cost {:.2}, built in {:.2?}",
+            self.nodes,
+            self.leaves,
+            self.max_depth,
+            self.leaf_objects as f64 / self.leaves.max(1) as f64,
+            self.sah_cost(),
+            self.build_time,
+        )
+    }
+}
 
 pub struct BVHNode {
-    left: Arc<Box<dyn Hittable>>,
-    right: Option<Arc<Box<dyn Hittable>>>,
+    left: Child,
+    right: Option<Child>,
     bbox: AABB,
+    // How many objects the children are, or 0 if they're other nodes
+    leaf_size: usize,
+    // Only set on the root
+    info: Option<Box<BuildInfo>>,
+}
+
+// What a node points at. Kept typed so refit can take the tree apart again for a rebuild
+enum Child {
+    Node(Box<BVHNode>),
+    Object(Box<dyn Hittable>),
+    // A big leaf, or the unbounded objects next to the tree
+    List(HittableList),
+}
+
+impl Child {
+    fn get(&self) -> &dyn Hittable {
+        match self {
+            Child::Node(node) => &**node,
+            Child::Object(object) => &**object,
+            Child::List(list) => list,
+        }
+    }
+
+    fn get_mut(&mut self) -> &mut dyn Hittable {
+        match self {
+            Child::Node(node) => &mut **node,
+            Child::Object(object) => &mut **object,
+            Child::List(list) => list,
+        }
+    }
+
+    fn into_objects(self, objects: &mut Vec<Box<dyn Hittable>>) {
+        match self {
+            Child::Node(node) => node.into_objects(objects),
+            Child::Object(object) => objects.push(object),
+            Child::List(list) => objects.extend(list.objects()),
+        }
+    }
+}
+
+// What the root remembers about how the tree was built, so refit can tell when to build it again
+struct BuildInfo {
+    builder: BVHBuilder,
+    time: Duration,
+    // SAH cost right after the last build, which refit compares against
+    cost: f64,
 }
 
 impl BVHNode {
-    pub fn new(mut objects: Vec<Box<dyn Hittable>>, debug_file: &str) -> Self {
-        Self::new_internal(objects, 0, Debugger::new(debug_file))
+    pub fn new(objects: Vec<Box<dyn Hittable>>, debugger: Option<Debugger>) -> Self {
+        Self::with_builder(objects, BVHBuilder::Median, debugger)
+    }
+
+    // Without a debugger the build isn't logged anywhere
+    pub fn with_builder(objects: Vec<Box<dyn Hittable>>, builder: BVHBuilder, debugger: Option<Debugger>) -> Self {
+        Self::build(objects, builder, |bounded| Self::new_internal(bounded, builder, 0, debugger.unwrap_or_else(Debugger::sink)))
     }
-    
-    fn new_internal(mut objects: Vec<Box<dyn Hittable>>, level: usize, debugger: Debugger) -> Self {
+
+    fn build(objects: Vec<Box<dyn Hittable>>, builder: BVHBuilder, build_tree: impl FnOnce(Vec<Box<dyn Hittable>>) -> Self) -> Self {
+        let begin = Instant::now();
+
+        // Infinite bounds would swallow every box above them, so unbounded objects sit next to the tree instead
+        let (bounded, unbounded): (Vec<_>, Vec<_>) = objects.into_iter()
+            .partition(|object| object.bounding().is_bounded());
+        let mut root = if unbounded.is_empty() {
+            build_tree(bounded)
+        } else {
+            let mut list = HittableList::new();
+            for object in unbounded {
+                list.add(object);
+            }
+            let left = Child::List(list);
+            if bounded.is_empty() {
+                let bbox = left.get().bounding().clone();
+                BVHNode { left, right: None, bbox, leaf_size: 0, info: None }
+            } else {
+                let tree = build_tree(bounded);
+                let bbox = AABB::enclose(left.get().bounding(), &tree.bbox);
+                BVHNode {
+                    left,
+                    right: Some(Child::Node(Box::new(tree))),
+                    bbox,
+                    leaf_size: 0,
+                    info: None,
+                }
+            }
+        };
+
+        let time = begin.elapsed();
+        let cost = BVHStats::of(&root, builder.cost_ratio()).sah_cost();
+        root.info = Some(Box::new(BuildInfo {builder, time, cost}));
+        root
+    }
+
+    // Fits every box to `time`, e.g. one frame's shutter, keeping the tree as it is. If that makes the tree
+    // too slow, it's built again around where the objects are now
+    pub fn refit(&mut self, time: &Interval) {
+        let mut bbox = AABB::EMPTY;
+        for child in [Some(&mut self.left), self.right.as_mut()].into_iter().flatten() {
+            bbox = AABB::enclose(&bbox, &child.get_mut().refit_bounds(time));
+        }
+        self.bbox = bbox;
+
+        let Some(info) = &self.info else {return;};
+        let builder = info.builder;
+        if BVHStats::of(self, builder.cost_ratio()).sah_cost() > info.cost * REBUILD_THRESHOLD {
+            let empty = BVHNode {left: Child::List(HittableList::new()), right: None, bbox: AABB::EMPTY, leaf_size: 0, info: None};
+            let mut objects = Vec::new();
+            std::mem::replace(self, empty).into_objects(&mut objects);
+            *self = Self::build(objects, builder, |bounded| {
+                Self::from_tree(build_tree(bounded, builder, &|object: &Box<dyn Hittable>| *object.bounding()))
+            });
+        }
+    }
+
+    // Takes the tree apart, handing back the objects it was built from
+    fn into_objects(self, objects: &mut Vec<Box<dyn Hittable>>) {
+        for child in [Some(self.left), self.right].into_iter().flatten() {
+            child.into_objects(objects);
+        }
+    }
+
+    fn from_tree(tree: BuildNode<Box<dyn Hittable>>) -> Self {
+        match tree {
+            BuildNode::Leaf {bbox, items} => Self::leaf(items, bbox),
+            BuildNode::Interior {bbox, left, right} => BVHNode {
+                left: Child::Node(Box::new(Self::from_tree(*left))),
+                right: Some(Child::Node(Box::new(Self::from_tree(*right)))),
+                bbox,
+                leaf_size: 0,
+                info: None,
+            },
+        }
+    }
+
+    fn new_internal(mut objects: Vec<Box<dyn Hittable>>, builder: BVHBuilder, level: usize, debugger: Debugger) -> Self {
         let mut bbox = AABB::EMPTY;
         for object in &objects {
             bbox = AABB::enclose(&bbox, object.bounding())
         }
-        
-        let axis = bbox.longest_axis();
-        
-        let compare = match axis {
-            0 => |a: &Box<dyn Hittable>, b: &Box<dyn Hittable>| {
-                let a_interval = a.bounding().x;
-                let b_interval = b.bounding().x;
-                a_interval.min.partial_cmp(&b_interval.min).unwrap()
-            },
-            1 => |a: &Box<dyn Hittable>, b: &Box<dyn Hittable>| {
-                let a_interval = a.bounding().y;
-                let b_interval = b.bounding().y;
-                a_interval.min.partial_cmp(&b_interval.min).unwrap()
-            },
-            2 => |a: &Box<dyn Hittable>, b: &Box<dyn Hittable>| {
-                let a_interval = a.bounding().z;
-                let b_interval = b.bounding().z;
-                a_interval.min.partial_cmp(&b_interval.min).unwrap()
-            },
-            _ => unreachable!()
+
+        let Some(right_objs) = split(&mut objects, &bbox, builder, &|object| *object.bounding()) else {
+            return Self::leaf(objects, bbox);
         };
-        
-        let len = objects.len();
-        
-        if len == 1 {
-            let obj = objects.remove(0);
-            let bbox = obj.bounding().clone();
-            BVHNode {
-                left: Arc::new(obj),
+
+        let left = Child::Node(
+            Box::new(BVHNode::new_internal(objects, builder, level + 1, debugger.clone()))
+        );
+        let right = Child::Node(
+            Box::new(BVHNode::new_internal(right_objs, builder, level + 1, debugger.clone()))
+        );
+        BVHNode {
+            left,
+            right: Some(right),
+            bbox,
+            leaf_size: 0,
+            info: None,
+        }
+    }
+
+    fn leaf(mut objects: Vec<Box<dyn Hittable>>, bbox: AABB) -> Self {
+        let leaf_size = objects.len();
+        match leaf_size {
+            1 => BVHNode {
+                left: Child::Object(objects.remove(0)),
                 right: None,
                 bbox,
+                leaf_size,
+                info: None,
+            },
+            2 => {
+                let right = objects.remove(1);
+                let left = objects.remove(0);
+                BVHNode {
+                    left: Child::Object(left),
+                    right: Some(Child::Object(right)),
+                    bbox,
+                    leaf_size,
+                    info: None,
+                }
+            },
+            _ => {
+                let mut list = HittableList::new();
+                for object in objects {
+                    list.add(object);
+                }
+                BVHNode {
+                    left: Child::List(list),
+                    right: None,
+                    bbox,
+                    leaf_size,
+                    info: None,
+                }
+            },
+        }
+    }
+}
+
+fn centroid(bbox: &AABB, axis: usize) -> f64 {
+    let interval = bbox.axis(axis);
+    (interval.min + interval.max) / 2.0
+}
+
+// Sorts along the longest axis and hands back the upper half
+pub fn median_split<T>(items: &mut Vec<T>, bbox: &AABB, bounds: &impl Fn(&T) -> AABB) -> Vec<T> {
+    let axis = bbox.longest_axis();
+    items.sort_by(|a, b| bounds(a).axis(axis).min.partial_cmp(&bounds(b).axis(axis).min).unwrap());
+    let mid = items.len() / 2;
+    items.split_off(mid)
+}
+
+// Bins the centroids on every axis and picks the cheapest split between bins. Returns None when a leaf is cheaper
+pub fn sah_split<T>(items: &mut Vec<T>, bbox: &AABB, options: &SahOptions, bounds: &impl Fn(&T) -> AABB) -> Option<Vec<T>> {
+    let len = items.len();
+    let bins = options.bins.max(2);
+    let parent_area = bbox.surface_area();
+
+    let mut centroids = AABB::EMPTY;
+    for item in items.iter() {
+        let c = bounds(item).center();
+        centroids = AABB::enclose(&centroids, &AABB::from(c, c));
+    }
+    let bin_of = |item: &T, axis: usize| {
+        let extent = centroids.axis(axis);
+        (((centroid(&bounds(item), axis) - extent.min) / extent.size() * bins as f64) as usize).min(bins - 1)
+    };
+
+    // (cost, axis, last bin on the left)
+    let mut best: Option<(f64, usize, usize)> = None;
+    for axis in 0..3 {
+        if centroids.axis(axis).size() <= 0.0 || parent_area <= 0.0 {continue;}
+
+        let mut counts = vec![0; bins];
+        let mut boxes = vec![AABB::EMPTY; bins];
+        for item in items.iter() {
+            let bin = bin_of(item, axis);
+            counts[bin] += 1;
+            boxes[bin] = AABB::enclose(&boxes[bin], &bounds(item));
+        }
+
+        // Sweep from the right first, so the left sweep can price every split as it goes
+        let mut right_cost = vec![0.0; bins];
+        let (mut right_box, mut right_count) = (AABB::EMPTY, 0);
+        for bin in (1..bins).rev() {
+            right_box = AABB::enclose(&right_box, &boxes[bin]);
+            right_count += counts[bin];
+            right_cost[bin] = right_box.surface_area() * right_count as f64;
+        }
+
+        let (mut left_box, mut left_count) = (AABB::EMPTY, 0);
+        for bin in 0..(bins - 1) {
+            left_box = AABB::enclose(&left_box, &boxes[bin]);
+            left_count += counts[bin];
+            if left_count == 0 || left_count == len {continue;}
+
+            let cost = options.cost_ratio + (left_box.surface_area() * left_count as f64 + right_cost[bin + 1]) / parent_area;
+            if best.is_none_or(|(best_cost, _, _)| cost < best_cost) {
+                best = Some((cost, axis, bin));
             }
-        } else if len == 2 {
-            let left = objects.remove(0);
-            let right = objects.remove(0);
-            let left_bound = left.bounding().clone();
-            let right_bound = right.bounding().clone();
-            BVHNode {
-                left: Arc::new(left),
-                right: Some(Arc::new(right)),
-                bbox: AABB::enclose(&left_bound, &right_bound)
-            }
-        } else {
-            objects[..].sort_by(compare);
-            let mid = (len)/2;
-            let right_objs = objects.split_off(mid);
-            let left: Arc<Box<dyn Hittable>> = Arc::new(
-                Box::new(BVHNode::new_internal(objects, level + 1, debugger.clone()))
-            );
-            let right: Arc<Box<dyn Hittable>> = Arc::new(
-                Box::new(BVHNode::new_internal(right_objs, level + 1, debugger.clone()))
-            );
-            BVHNode {
-                left,
-                right: Some(right),
-                bbox,
+        }
+    }
+
+    let Some((cost, axis, split)) = best else {
+        // Every centroid is in the same place, so no split is any better than another
+        return if len <= options.max_leaf_size {None} else {Some(median_split(items, bbox, bounds))};
+    };
+    if len <= options.max_leaf_size && len as f64 <= cost {
+        return None;
+    }
+
+    let (left, right): (Vec<_>, Vec<_>) = items.drain(..).partition(|item| bin_of(item, axis) <= split);
+    *items = left;
+    Some(right)
+}
+
+// The split both BVHNode and LinearBVH make, or None for a leaf
+pub fn split<T>(items: &mut Vec<T>, bbox: &AABB, builder: BVHBuilder, bounds: &impl Fn(&T) -> AABB) -> Option<Vec<T>> {
+    if items.len() <= 2 {
+        return None;
+    }
+    match builder {
+        BVHBuilder::Median => Some(median_split(items, bbox, bounds)),
+        BVHBuilder::Sah(options) => sah_split(items, bbox, &options, bounds),
+    }
+}
+
+// A built tree before it's turned into nodes, so BVHNode and LinearBVH can share the building
+pub enum BuildNode<T> {
+    Leaf {bbox: AABB, items: Vec<T>},
+    Interior {bbox: AABB, left: Box<BuildNode<T>>, right: Box<BuildNode<T>>},
+}
+
+impl<T> BuildNode<T> {
+    pub fn bbox(&self) -> &AABB {
+        match self {
+            BuildNode::Leaf {bbox, ..} | BuildNode::Interior {bbox, ..} => bbox,
+        }
+    }
+
+    // Edges from here down to the deepest leaf
+    pub fn depth(&self) -> usize {
+        match self {
+            BuildNode::Leaf {..} => 0,
+            BuildNode::Interior {left, right, ..} => 1 + left.depth().max(right.depth()),
+        }
+    }
+}
+
+fn enclose_all<T>(items: &[T], bounds: &impl Fn(&T) -> AABB) -> AABB {
+    items.iter().fold(AABB::EMPTY, |bbox, item| AABB::enclose(&bbox, &bounds(item)))
+}
+
+pub fn build_tree<T>(mut items: Vec<T>, builder: BVHBuilder, bounds: &impl Fn(&T) -> AABB) -> BuildNode<T> {
+    let bbox = enclose_all(&items, bounds);
+    match split(&mut items, &bbox, builder, bounds) {
+        None => BuildNode::Leaf {bbox, items},
+        Some(right) => BuildNode::Interior {
+            bbox,
+            left: Box::new(build_tree(items, builder, bounds)),
+            right: Box::new(build_tree(right, builder, bounds)),
+        },
+    }
+}
+
+// For trees that keep their objects in one array and point into it. The jobs only need the boxes, not the objects
+// A lopsided SAH tree can be deeper than STACK_SIZE, so those are built again with median splits, which halve
+// the objects every level
+pub fn build_index_tree(bounds: Vec<AABB>, builder: BVHBuilder, threadpool: Option<&ThreadPool>) -> BuildNode<u32> {
+    let build = |bounds: Vec<AABB>, builder| {
+        let items = (0..bounds.len() as u32).collect();
+        match threadpool {
+            Some(threadpool) => build_tree_parallel(items, builder, move |&i: &u32| bounds[i as usize], threadpool),
+            None => build_tree(items, builder, &|&i: &u32| bounds[i as usize]),
+        }
+    };
+    let tree = build(bounds.clone(), builder);
+    if tree.depth() <= STACK_SIZE {tree} else {build(bounds, BVHBuilder::Median)}
+}
+
+// What a build job hands back for a subtree: either all of it, or its split and where the halves went
+enum Piece<T> {
+    Done(BuildNode<T>),
+    Split {bbox: AABB, left: usize, right: usize},
+}
+
+// Splits are made by the same function in the same order as build_tree, so the tree comes out the same.
+// Jobs never wait on each other; only the calling thread waits, while it puts the pieces back together
+pub fn build_tree_parallel<T, F>(items: Vec<T>, builder: BVHBuilder, bounds: F, threadpool: &ThreadPool) -> BuildNode<T>
+    where T: Send + 'static, F: Fn(&T) -> AABB + Send + Sync + 'static {
+    if items.len() < PARALLEL_THRESHOLD {
+        return build_tree(items, builder, &bounds);
+    }
+
+    let (sender, receiver) = mpsc::channel();
+    let next_id = Arc::new(AtomicUsize::new(1));
+    spawn_build(0, items, builder, Arc::new(bounds), threadpool, sender, next_id);
+
+    let mut pieces = HashMap::new();
+    let mut outstanding = 1;
+    while outstanding > 0 {
+        let (id, piece) = receiver.recv().expect("A BVH build job panicked!");
+        if let Piece::Split {..} = piece {
+            outstanding += 2;
+        }
+        outstanding -= 1;
+        pieces.insert(id, piece);
+    }
+    assemble(0, &mut pieces)
+}
+
+fn spawn_build<T, F>(
+    id: usize,
+    mut items: Vec<T>,
+    builder: BVHBuilder,
+    bounds: Arc<F>,
+    threadpool: &ThreadPool,
+    sender: Sender<(usize, Piece<T>)>,
+    next_id: Arc<AtomicUsize>,
+) where T: Send + 'static, F: Fn(&T) -> AABB + Send + Sync + 'static {
+    let pool = threadpool.clone();
+    threadpool.execute(move || {
+        let bbox = enclose_all(&items, &*bounds);
+        let Some(right) = split(&mut items, &bbox, builder, &*bounds) else {
+            sender.send((id, Piece::Done(BuildNode::Leaf {bbox, items}))).unwrap();
+            return;
+        };
+
+        let left_id = next_id.fetch_add(2, Ordering::Relaxed);
+        sender.send((id, Piece::Split {bbox, left: left_id, right: left_id + 1})).unwrap();
+        for (child_id, child) in [(left_id, items), (left_id + 1, right)] {
+            if child.len() >= PARALLEL_THRESHOLD {
+                spawn_build(child_id, child, builder, bounds.clone(), &pool, sender.clone(), next_id.clone());
+            } else {
+                sender.send((child_id, Piece::Done(build_tree(child, builder, &*bounds)))).unwrap();
             }
         }
+    });
+}
+
+fn assemble<T>(id: usize, pieces: &mut HashMap<usize, Piece<T>>) -> BuildNode<T> {
+    match pieces.remove(&id).expect("Missing a piece of the BVH!") {
+        Piece::Done(tree) => tree,
+        Piece::Split {bbox, left, right} => BuildNode::Interior {
+            bbox,
+            left: Box::new(assemble(left, pieces)),
+            right: Box::new(assemble(right, pieces)),
+        },
     }
 }
 
 impl Hittable for BVHNode {
     fn hit(&self, ray: &Ray, ray_t: &Interval) -> Option<Hit> {
         self.bbox.test(ray, ray_t)?;
-        if let None = self.right {return self.left.hit(ray, ray_t)};
+        if let None = self.right {return self.left.get().hit(ray, ray_t)};
 
-        let right = self.right.clone().expect("Impossible!");
+        let right = self.right.as_ref().expect("Impossible!").get();
 
-        let left_hit = self.left.hit(ray, ray_t);
+        let left_hit = self.left.get().hit(ray, ray_t);
         match left_hit {
             None => return right.hit(ray, ray_t),
             Some(left_rec) => {
@@ -102,9 +524,38 @@ impl Hittable for BVHNode {
     }
 
     fn objects(&self) -> usize {
-        self.left.objects() + match &self.right {
+        self.left.get().objects() + match &self.right {
             None => 0,
-            Some(obj) => obj.objects()
+            Some(obj) => obj.get().objects()
+        }
+    }
+
+    fn refit_bounds(&mut self, time: &Interval) -> AABB {
+        self.refit(time);
+        self.bbox
+    }
+
+    fn bvh_stats(&self, depth: usize, stats: &mut BVHStats) {
+        if let Some(info) = &self.info {
+            stats.build_time += info.time;
+        }
+        stats.nodes += 1;
+        stats.max_depth = stats.max_depth.max(depth);
+
+        // The node holding unbounded objects doesn't count towards the cost
+        let area = if self.bbox.is_bounded() {self.bbox.surface_area()} else {0.0};
+        stats.root_area = stats.root_area.max(area);
+
+        if self.leaf_size > 0 {
+            stats.leaves += 1;
+            stats.leaf_objects += self.leaf_size;
+            stats.leaf_area += area * self.leaf_size as f64;
+        } else {
+            stats.interior_area += area;
+            self.left.get().bvh_stats(depth + 1, stats);
+            if let Some(right) = &self.right {
+                right.get().bvh_stats(depth + 1, stats);
+            }
         }
     }
-}
\ No newline at end of file
+}
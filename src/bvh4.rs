@@ -0,0 +1,309 @@
+use std::{simd::{cmp::SimdPartialOrd, num::SimdFloat, Mask, Simd}, time::{Duration, Instant}};
+
+use threadpool::ThreadPool;
+
+use crate::{aabb::AABB, bvh::{build_index_tree, BuildNode, BVHBuilder, BVHStats, REBUILD_THRESHOLD, STACK_SIZE}, hit::{Hit, Hittable, HittableList}, interval::Interval, packet::{hit_packets, PacketNode, PacketTree, MAX_CHILDREN}, ray::Ray, vector::Vector3};
+
+// Marks a lane with no child. Its box is at infinity, so no ray ever hits it
+const EMPTY: u32 = u32::MAX;
+
+// The tree is no deeper than the binary one, and traversal keeps at most three siblings waiting per level
+// plus the four children of the node it's in
+const TRAVERSAL_STACK: usize = 3 * STACK_SIZE + 1;
+
+// Four children's boxes side by side, one lane each, so one ray can be tested against all of them at once
+pub struct Bvh4Node {
+    min: [Simd<f64, 4>; 3],
+    max: [Simd<f64, 4>; 3],
+    // Node index for interior children, first entry in `indices` for leaves
+    children: [u32; 4],
+    // 0 for interior children
+    counts: [u32; 4],
+}
+
+impl Bvh4Node {
+    pub fn new(boxes: &[AABB]) -> Self {
+        let mut min = [Simd::splat(f64::INFINITY); 3];
+        let mut max = [Simd::splat(f64::INFINITY); 3];
+        for (lane, bbox) in boxes.iter().enumerate() {
+            for axis in 0..3 {
+                min[axis][lane] = bbox.axis(axis).min;
+                max[axis][lane] = bbox.axis(axis).max;
+            }
+        }
+        Bvh4Node { min, max, children: [EMPTY; 4], counts: [0; 4] }
+    }
+
+    fn child_bbox(&self, lane: usize) -> AABB {
+        let interval = |axis: usize| Interval {min: self.min[axis][lane], max: self.max[axis][lane]};
+        AABB {x: interval(0), y: interval(1), z: interval(2)}
+    }
+
+    fn set_child_bbox(&mut self, lane: usize, bbox: &AABB) {
+        for axis in 0..3 {
+            self.min[axis][lane] = bbox.axis(axis).min;
+            self.max[axis][lane] = bbox.axis(axis).max;
+        }
+    }
+
+    fn bbox(&self) -> AABB {
+        (0..4).filter(|&lane| self.children[lane] != EMPTY)
+            .fold(AABB::EMPTY, |bbox, lane| AABB::enclose(&bbox, &self.child_bbox(lane)))
+    }
+
+    // The same slab test as AABB::test, on all four boxes. Returns which were hit and where the ray enters them
+    pub fn test(&self, origin: &[Simd<f64, 4>; 3], inv_dir: &[Simd<f64, 4>; 3], ray_t: &Interval) -> (Mask<i64, 4>, Simd<f64, 4>) {
+        let mut min = Simd::splat(ray_t.min);
+        let mut max = Simd::splat(ray_t.max);
+        for axis in 0..3 {
+            let t0 = (self.min[axis] - origin[axis]) * inv_dir[axis];
+            let t1 = (self.max[axis] - origin[axis]) * inv_dir[axis];
+            min = min.simd_max(t0.simd_min(t1));
+            max = max.simd_min(t0.simd_max(t1));
+        }
+        (max.simd_gt(min), min)
+    }
+}
+
+// The binary tree collapsed so every node has up to four children. Fewer, wider nodes mean fewer steps
+// down the tree, and each step is one SIMD box test
+pub struct Bvh4 {
+    objects: Vec<Box<dyn Hittable>>,
+    indices: Vec<u32>,
+    nodes: Vec<Bvh4Node>,
+    unbounded: HittableList,
+    bbox: AABB,
+    builder: BVHBuilder,
+    // Kept from new_parallel, so rebuilds by refit are parallel too
+    threadpool: Option<ThreadPool>,
+    build_time: Duration,
+    // SAH cost right after the last build, which refit compares against
+    cost: f64,
+}
+
+impl Bvh4 {
+    pub fn new(objects: Vec<Box<dyn Hittable>>, builder: BVHBuilder) -> Self {
+        Self::build(objects, builder, None)
+    }
+
+    pub fn new_parallel(objects: Vec<Box<dyn Hittable>>, builder: BVHBuilder, threadpool: &ThreadPool) -> Self {
+        Self::build(objects, builder, Some(threadpool))
+    }
+
+    fn build(objects: Vec<Box<dyn Hittable>>, builder: BVHBuilder, threadpool: Option<&ThreadPool>) -> Self {
+        let begin = Instant::now();
+
+        let (objects, unbounded_objects): (Vec<_>, Vec<_>) = objects.into_iter()
+            .partition(|object| object.bounding().is_bounded());
+        let mut unbounded = HittableList::new();
+        for object in unbounded_objects {
+            unbounded.add(object);
+        }
+
+        let mut bvh = Bvh4 {
+            indices: Vec::with_capacity(objects.len()),
+            nodes: Vec::with_capacity(objects.len() / 2 + 1),
+            objects,
+            bbox: *unbounded.bounding(),
+            unbounded,
+            builder,
+            threadpool: threadpool.cloned(),
+            build_time: Duration::ZERO,
+            cost: 0.0,
+        };
+        if !bvh.objects.is_empty() {
+            let tree = build_index_tree(bvh.objects.iter().map(|object| *object.bounding()).collect(), builder, threadpool);
+            let root = match tree {
+                BuildNode::Interior {left, right, ..} => vec![*left, *right],
+                leaf => vec![leaf],
+            };
+            bvh.collapse(root);
+            bvh.bbox = AABB::enclose(&bvh.bbox, &bvh.nodes[0].bbox());
+        }
+        bvh.build_time = begin.elapsed();
+        bvh.cost = BVHStats::of(&bvh, builder.cost_ratio()).sah_cost();
+        bvh
+    }
+
+    // Fits every box to `time` and rebuilds once the tree gets too slow, the same way LinearBVH::refit does
+    pub fn refit(&mut self, time: &Interval) {
+        let bounds: Vec<AABB> = self.objects.iter_mut().map(|object| object.refit_bounds(time)).collect();
+        self.bbox = self.unbounded.refit_bounds(time);
+        if self.nodes.is_empty() {return;}
+
+        // Children always come after their parent, so going backwards refits them first
+        for index in (0..self.nodes.len()).rev() {
+            let (children, counts) = (self.nodes[index].children, self.nodes[index].counts);
+            for lane in (0..4).filter(|&lane| children[lane] != EMPTY) {
+                let (child, count) = (children[lane] as usize, counts[lane] as usize);
+                let bbox = if count > 0 {
+                    self.indices[child..(child + count)].iter()
+                        .fold(AABB::EMPTY, |bbox, &i| AABB::enclose(&bbox, &bounds[i as usize]))
+                } else {
+                    self.nodes[child].bbox()
+                };
+                self.nodes[index].set_child_bbox(lane, &bbox);
+            }
+        }
+        self.bbox = AABB::enclose(&self.bbox, &self.nodes[0].bbox());
+
+        if BVHStats::of(self, self.builder.cost_ratio()).sah_cost() > self.cost * REBUILD_THRESHOLD {
+            let mut objects = std::mem::take(&mut self.objects);
+            objects.extend(std::mem::replace(&mut self.unbounded, HittableList::new()).objects());
+            let threadpool = self.threadpool.take();
+            *self = Self::build(objects, self.builder, threadpool.as_ref());
+        }
+    }
+
+    fn collapse(&mut self, mut children: Vec<BuildNode<u32>>) -> u32 {
+        // Pull up the grandchildren under the biggest interior child until there are four
+        while children.len() < 4 {
+            let biggest = children.iter().enumerate()
+                .filter(|(_, child)| matches!(child, BuildNode::Interior {..}))
+                .max_by(|(_, a), (_, b)| a.bbox().surface_area().partial_cmp(&b.bbox().surface_area()).unwrap())
+                .map(|(i, _)| i);
+            let Some(i) = biggest else {break;};
+            let BuildNode::Interior {left, right, ..} = children.remove(i) else {unreachable!()};
+            children.insert(i, *right);
+            children.insert(i, *left);
+        }
+
+        let boxes: Vec<AABB> = children.iter().map(|child| *child.bbox()).collect();
+        let index = self.nodes.len();
+        self.nodes.push(Bvh4Node::new(&boxes));
+
+        for (lane, child) in children.into_iter().enumerate() {
+            let (child, count) = match child {
+                BuildNode::Leaf {items, ..} => {
+                    let start = self.indices.len() as u32;
+                    let count = items.len() as u32;
+                    self.indices.extend(items);
+                    (start, count)
+                },
+                BuildNode::Interior {left, right, ..} => (self.collapse(vec![*left, *right]), 0),
+            };
+            self.nodes[index].children[lane] = child;
+            self.nodes[index].counts[lane] = count;
+        }
+        index as u32
+    }
+
+    fn node_stats(&self, index: usize, depth: usize, stats: &mut BVHStats) {
+        let node = &self.nodes[index];
+        let area = node.bbox().surface_area();
+        stats.nodes += 1;
+        stats.max_depth = stats.max_depth.max(depth);
+        stats.root_area = stats.root_area.max(area);
+        stats.interior_area += area;
+
+        for lane in (0..4).filter(|&lane| node.children[lane] != EMPTY) {
+            let count = node.counts[lane] as usize;
+            if count > 0 {
+                stats.leaves += 1;
+                stats.leaf_objects += count;
+                stats.leaf_area += node.child_bbox(lane).surface_area() * count as f64;
+                stats.max_depth = stats.max_depth.max(depth + 1);
+            } else {
+                self.node_stats(node.children[lane] as usize, depth + 1, stats);
+            }
+        }
+    }
+}
+
+impl Hittable for Bvh4 {
+    fn hit(&self, ray: &Ray, ray_t: &Interval) -> Option<Hit> {
+        let mut closest = self.unbounded.hit(ray, ray_t);
+        let mut range = Interval {min: ray_t.min, max: closest.as_ref().map_or(ray_t.max, |hit| hit.t)};
+        if self.nodes.is_empty() {return closest;}
+
+        let origin = [ray.origin.x(), ray.origin.y(), ray.origin.z()].map(Simd::splat);
+        let inv_dir = [1.0 / ray.dir.x(), 1.0 / ray.dir.y(), 1.0 / ray.dir.z()].map(Simd::splat);
+
+        // (child, count, where the ray enters it), so children found to be behind a closer hit get skipped
+        let mut stack = [(0_u32, 0_u32, 0.0); TRAVERSAL_STACK];
+        stack[0] = (0, 0, range.min);
+        let mut depth = 1;
+        while depth > 0 {
+            depth -= 1;
+            let (child, count, entry) = stack[depth];
+            if entry >= range.max {continue;}
+
+            if count > 0 {
+                for &i in &self.indices[(child as usize)..((child + count) as usize)] {
+                    if let Some(hit) = self.objects[i as usize].hit(ray, &range) {
+                        range.max = hit.t;
+                        closest = Some(hit);
+                    }
+                }
+                continue;
+            }
+
+            let node = &self.nodes[child as usize];
+            let (mask, entries) = node.test(&origin, &inv_dir, &range);
+            let start = depth;
+            for lane in 0..4 {
+                if mask.test(lane) {
+                    stack[depth] = (node.children[lane], node.counts[lane], entries[lane]);
+                    depth += 1;
+                }
+            }
+            // Nearest on top
+            stack[start..depth].sort_unstable_by(|a, b| b.2.total_cmp(&a.2));
+        }
+        closest
+    }
+
+    fn hit_all(&self, rays: &[Ray], ray_t: &Interval) -> Vec<Option<Hit>> {
+        hit_packets(self, rays, ray_t)
+    }
+
+    fn bounding(&self) -> &AABB {
+        &self.bbox
+    }
+
+    fn objects(&self) -> usize {
+        self.objects.iter().map(|object| object.objects()).sum::<usize>() + Hittable::objects(&self.unbounded)
+    }
+
+    fn refit_bounds(&mut self, time: &Interval) -> AABB {
+        self.refit(time);
+        self.bbox
+    }
+
+    fn bvh_stats(&self, depth: usize, stats: &mut BVHStats) {
+        stats.build_time += self.build_time;
+        if !self.nodes.is_empty() {
+            self.node_stats(0, depth, stats);
+        }
+    }
+}
+
+impl PacketTree for Bvh4 {
+    fn root(&self) -> Option<(AABB, PacketNode)> {
+        (!self.nodes.is_empty()).then(|| (self.nodes[0].bbox(), PacketNode::Interior(0)))
+    }
+
+    fn children(&self, node: usize, dir: &Vector3, children: &mut [(AABB, PacketNode); MAX_CHILDREN]) -> usize {
+        let node = &self.nodes[node];
+        let mut count = 0;
+        for lane in (0..4).filter(|&lane| node.children[lane] != EMPTY) {
+            let child = node.children[lane] as usize;
+            let packet_node = match node.counts[lane] {
+                0 => PacketNode::Interior(child),
+                n => PacketNode::Leaf(child, n as usize),
+            };
+            children[count] = (node.child_bbox(lane), packet_node);
+            count += 1;
+        }
+        children[..count].sort_unstable_by(|a, b| a.0.center().dot(dir).total_cmp(&b.0.center().dot(dir)));
+        count
+    }
+
+    fn leaf_object(&self, entry: usize) -> &dyn Hittable {
+        &*self.objects[self.indices[entry] as usize]
+    }
+
+    fn unbounded(&self) -> &HittableList {
+        &self.unbounded
+    }
+}
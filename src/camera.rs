@@ -1,7 +1,7 @@
 use rand::random;
 use threadpool::ThreadPool;
 
-use crate::{quality::QualityOptions, ray::Ray, util::degrees_to_radians, vector::{Point3, Vector3}};
+use crate::{interval::Interval, quality::QualityOptions, ray::Ray, util::degrees_to_radians, vector::{Point3, Vector3}};
 
 #[derive(Clone)]
 pub struct Camera {
@@ -14,6 +14,8 @@ pub struct Camera {
     // vup: Vector3,
     defocus_angle: f64,
     // focus_dist: f64,
+    // Times rays are spread over, for motion blur
+    pub shutter: Interval,
 
     // "Private"
     pub img_height: usize,
@@ -85,6 +87,7 @@ impl Camera {
             // vup,
             defocus_angle,
             // focus_dist,
+            shutter: Interval {min: 0.0, max: 1.0},
             
             // "Private"
             img_height,
@@ -101,6 +104,12 @@ impl Camera {
         }
     }
 
+    // One frame of an animation, e.g. 0.5..1.0 for the second of two
+    pub fn with_shutter(mut self, shutter: Interval) -> Self {
+        self.shutter = shutter;
+        self
+    }
+
     pub fn get_ray(&self, i: usize, j: usize) -> Ray {
         let offset = self.sample_square();
         let pixel_sample = self.pixel00
@@ -110,11 +119,15 @@ impl Camera {
         let origin = if self.defocus_angle <= 0.0
             {self.pos} else {self.defocus_sample()};
         let dir = pixel_sample - origin;
-        let time = random();
+        let time = self.shutter.min + random::<f64>() * self.shutter.size();
 
         Ray { origin, dir, time }
     }
 
+    pub fn pixel_rays(&self, i: usize, j: usize) -> PixelRays<'_> {
+        PixelRays { camera: self, i, j, remaining: self.samples_per_pixel }
+    }
+
     fn sample_square(&self) -> Vector3 {
         Vector3 (
             rand::random::<f64>() - 0.5,
@@ -133,6 +146,27 @@ impl Camera {
     }
 }
 
+pub struct PixelRays<'a> {
+    camera: &'a Camera,
+    i: usize,
+    j: usize,
+    remaining: usize,
+}
+
+impl Iterator for PixelRays<'_> {
+    type Item = Ray;
+
+    fn next(&mut self) -> Option<Ray> {
+        if self.remaining == 0 {return None;}
+        self.remaining -= 1;
+        Some(self.camera.get_ray(self.i, self.j))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
 pub struct CameraBuilder {
     quality: QualityOptions,
     img_aspect: f64,
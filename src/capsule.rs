@@ -0,0 +1,86 @@
+use std::{f64::consts::TAU, sync::Arc};
+
+use crate::{aabb::AABB, hit::{Hit, Hittable}, interval::Interval, material::Material, math::solve_quadratic, onb::Onb, ray::Ray, vector::{Point3, Vector3}};
+
+// Every point within radius of the segment from a to b. Locally the segment runs along z from 0 to length
+pub struct Capsule {
+    a: Point3,
+    frame: Onb,
+    length: f64,
+    radius: f64,
+    material: Arc<Box<dyn Material>>,
+    bbox: AABB,
+}
+
+impl Capsule {
+    pub fn new(a: Point3, b: Point3, radius: f64, material: Arc<Box<dyn Material>>) -> Self {
+        let r = Vector3::from_all(radius);
+        let bbox = AABB::enclose(&AABB::from(a - r, a + r), &AABB::from(b - r, b + r));
+        Capsule {
+            a,
+            frame: Onb::new(&(b - a)),
+            length: (b - a).length(),
+            radius,
+            material,
+            bbox,
+        }
+    }
+
+    pub fn as_box(self) -> Box<dyn Hittable> {
+        Box::new(self)
+    }
+}
+
+impl Hittable for Capsule {
+    fn hit(&self, ray: &Ray, ray_t: &Interval) -> Option<Hit> {
+        let o = self.frame.local(&(ray.origin - self.a));
+        let d = self.frame.local(&ray.dir);
+        let (r, len) = (self.radius, self.length);
+
+        let mut best: Option<(f64, Vector3)> = None;
+        let mut consider = |t: f64, p: Vector3| {
+            if ray_t.surrounds(t) && best.is_none_or(|(closest, _)| t < closest) {
+                best = Some((t, p));
+            }
+        };
+
+        // The open cylinder in the middle
+        let a = d.x() * d.x() + d.y() * d.y();
+        let b = 2.0 * (o.x() * d.x() + o.y() * d.y());
+        let c = o.x() * o.x() + o.y() * o.y() - r * r;
+        for t in solve_quadratic(a, b, c) {
+            let p = o + d * t;
+            if (0.0..=len).contains(&p.z()) {consider(t, p);}
+        }
+
+        // Half a sphere on each end
+        for center in [0.0, len] {
+            let oc = o - Vector3(0.0, 0.0, center);
+            for t in solve_quadratic(d.length_squared(), 2.0 * oc.dot(&d), oc.length_squared() - r * r) {
+                let p = o + d * t;
+                let outside = if center == 0.0 {p.z() <= 0.0} else {p.z() >= len};
+                if outside {consider(t, p);}
+            }
+        }
+
+        let (t, p) = best?;
+        // Away from the closest point on the segment
+        let normal = (p - Vector3(0.0, 0.0, p.z().clamp(0.0, len))) / r;
+        let outward_normal = self.frame.transform(&normal);
+        let front_face = ray.dir.dot(&outward_normal) < 0.0;
+        Some(Hit {
+            p: ray.at(t),
+            normal: if front_face {outward_normal} else {-outward_normal},
+            material: self.material.clone(),
+            t,
+            u: p.y().atan2(p.x()) / TAU + 0.5,
+            v: ((p.z() + r) / (len + 2.0 * r)).clamp(0.0, 1.0),
+            front_face,
+            color: None,
+        })
+    }
+
+    fn bounding(&self) -> &AABB {
+        &self.bbox
+    }
+}
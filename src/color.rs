@@ -1,8 +1,8 @@
-use std::{fs::File, io::{BufWriter, Write}, ops::{Add, AddAssign, Div, Mul, Sub}};
+use std::ops::{Add, AddAssign, Div, Mul, Sub};
 
 use rand::random;
 
-use crate::{interval::Interval, vector::Vector3};
+use crate::vector::Vector3;
 
 #[derive(Debug, Clone, Copy)]
 pub struct Color {
@@ -24,23 +24,6 @@ impl Pixel {
 }
 
 impl Color {
-    pub fn write(self, writer: &mut BufWriter<File>) {
-        let intensity = Interval {min: 0.0, max: 0.999};
-        let r_int: u8 = (256.0 * 
-            intensity.clamp(linear_to_gamma(self.r))
-        ) as u8;
-        let g_int: u8 = (256.0 * 
-            intensity.clamp(linear_to_gamma(self.g))
-        ) as u8;
-        let b_int: u8 = (256.0 * 
-            intensity.clamp(linear_to_gamma(self.b))
-        ) as u8;
-        
-        writer.write_all(
-            format!("{} {} {}\n", r_int, g_int, b_int).as_bytes()
-        ).unwrap();
-    }
-
     pub fn from_all(n: f64) -> Self {
         Color { r: n, g: n, b: n }
     }
@@ -62,10 +45,18 @@ impl Color {
     pub const CYAN : Color = Color {r: 0.0, g: 1.0, b: 1.0};
 }
 
-fn linear_to_gamma(component: f64) -> f64 {
+pub fn linear_to_gamma(component: f64) -> f64 {
     if component > 0.0 {component.sqrt()} else {0.0}
 }
 
+pub fn linear_to_srgb(component: f64) -> f64 {
+    if component <= 0.0031308 {
+        12.92 * component.max(0.0)
+    } else {
+        1.055 * component.powf(1.0 / 2.4) - 0.055
+    }
+}
+
 impl From<Vector3> for Color {
     fn from(value: Vector3) -> Self {
         Color { r: value.x(), g: value.y(), b: value.z() }
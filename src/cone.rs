@@ -0,0 +1,95 @@
+use std::{f64::consts::TAU, sync::Arc};
+
+use crate::{aabb::AABB, disk::disk_bounds, hit::{Hit, Hittable}, interval::Interval, material::Material, math::solve_quadratic, onb::Onb, ray::Ray, vector::{Point3, Vector3}};
+
+// A cone with a round base and its tip at apex. Locally the axis is z, from the base at 0 to the apex at height
+pub struct Cone {
+    base: Point3,
+    frame: Onb,
+    height: f64,
+    radius: f64,
+    cap: bool,
+    material: Arc<Box<dyn Material>>,
+    bbox: AABB,
+}
+
+impl Cone {
+    pub fn new(base: Point3, apex: Point3, radius: f64, material: Arc<Box<dyn Material>>) -> Self {
+        let axis = apex - base;
+        let bbox = AABB::enclose(&disk_bounds(base, axis, radius), &AABB::from(apex, apex)).pad(1e-4);
+        Cone {
+            base,
+            frame: Onb::new(&axis),
+            height: axis.length(),
+            radius,
+            cap: true,
+            material,
+            bbox,
+        }
+    }
+
+    pub fn with_cap(mut self, cap: bool) -> Self {
+        self.cap = cap;
+        self
+    }
+
+    pub fn as_box(self) -> Box<dyn Hittable> {
+        Box::new(self)
+    }
+}
+
+impl Hittable for Cone {
+    fn hit(&self, ray: &Ray, ray_t: &Interval) -> Option<Hit> {
+        let o = self.frame.local(&(ray.origin - self.base));
+        let d = self.frame.local(&ray.dir);
+        let (r, h) = (self.radius, self.height);
+
+        let mut best: Option<(f64, Vector3, (f64, f64))> = None;
+        let mut consider = |t: f64, normal: Vector3, uv: (f64, f64)| {
+            if ray_t.surrounds(t) && best.is_none_or(|(closest, _, _)| t < closest) {
+                best = Some((t, normal, uv));
+            }
+        };
+
+        // x^2 + y^2 = (k (h - z))^2, where k is how fast the radius shrinks
+        let k2 = (r / h) * (r / h);
+        let oh = h - o.z();
+        let a = d.x() * d.x() + d.y() * d.y() - k2 * d.z() * d.z();
+        let b = 2.0 * (o.x() * d.x() + o.y() * d.y() + k2 * oh * d.z());
+        let c = o.x() * o.x() + o.y() * o.y() - k2 * oh * oh;
+        for t in solve_quadratic(a, b, c) {
+            let p = o + d * t;
+            // The equation also describes a mirrored cone above the apex
+            if (0.0..=h).contains(&p.z()) {
+                let u = p.y().atan2(p.x()) / TAU + 0.5;
+                consider(t, Vector3(p.x(), p.y(), k2 * (h - p.z())).unit(), (u, p.z() / h));
+            }
+        }
+
+        if self.cap && d.z() != 0.0 {
+            let t = -o.z() / d.z();
+            let p = o + d * t;
+            if p.x() * p.x() + p.y() * p.y() <= r * r {
+                consider(t, Vector3(0.0, 0.0, -1.0), (0.5 + p.x() / (2.0 * r), 0.5 + p.y() / (2.0 * r)));
+            }
+        }
+
+        let (t, normal, (u, v)) = best?;
+        let outward_normal = self.frame.transform(&normal);
+        let front_face = ray.dir.dot(&outward_normal) < 0.0;
+        Some(Hit {
+            p: ray.at(t),
+            normal: if front_face {outward_normal} else {-outward_normal},
+            material: self.material.clone(),
+            t,
+            u,
+            v,
+            front_face,
+            color: None,
+        })
+    }
+
+    fn bounding(&self) -> &AABB {
+        &self.bbox
+    }
+}
@@ -0,0 +1,94 @@
+use std::{f64::consts::TAU, sync::Arc};
+
+use crate::{aabb::AABB, disk::disk_bounds, hit::{Hit, Hittable}, interval::Interval, material::Material, math::solve_quadratic, onb::Onb, ray::Ray, vector::{Point3, Vector3}};
+
+// A cylinder from base to top. Locally the axis is z, running from 0 to height
+pub struct Cylinder {
+    base: Point3,
+    frame: Onb,
+    height: f64,
+    radius: f64,
+    caps: bool,
+    material: Arc<Box<dyn Material>>,
+    bbox: AABB,
+}
+
+impl Cylinder {
+    pub fn new(base: Point3, top: Point3, radius: f64, material: Arc<Box<dyn Material>>) -> Self {
+        let axis = top - base;
+        let bbox = AABB::enclose(&disk_bounds(base, axis, radius), &disk_bounds(top, axis, radius)).pad(1e-4);
+        Cylinder {
+            base,
+            frame: Onb::new(&axis),
+            height: axis.length(),
+            radius,
+            caps: true,
+            material,
+            bbox,
+        }
+    }
+
+    pub fn with_caps(mut self, caps: bool) -> Self {
+        self.caps = caps;
+        self
+    }
+
+    pub fn as_box(self) -> Box<dyn Hittable> {
+        Box::new(self)
+    }
+}
+
+impl Hittable for Cylinder {
+    fn hit(&self, ray: &Ray, ray_t: &Interval) -> Option<Hit> {
+        let o = self.frame.local(&(ray.origin - self.base));
+        let d = self.frame.local(&ray.dir);
+        let (r, h) = (self.radius, self.height);
+
+        // Closest (t, local normal, uv) so far
+        let mut best: Option<(f64, Vector3, (f64, f64))> = None;
+        let mut consider = |t: f64, normal: Vector3, uv: (f64, f64)| {
+            if ray_t.surrounds(t) && best.is_none_or(|(closest, _, _)| t < closest) {
+                best = Some((t, normal, uv));
+            }
+        };
+
+        let a = d.x() * d.x() + d.y() * d.y();
+        let b = 2.0 * (o.x() * d.x() + o.y() * d.y());
+        let c = o.x() * o.x() + o.y() * o.y() - r * r;
+        for t in solve_quadratic(a, b, c) {
+            let p = o + d * t;
+            if (0.0..=h).contains(&p.z()) {
+                let u = p.y().atan2(p.x()) / TAU + 0.5;
+                consider(t, Vector3(p.x() / r, p.y() / r, 0.0), (u, p.z() / h));
+            }
+        }
+
+        if self.caps && d.z() != 0.0 {
+            for (z, side) in [(0.0, -1.0), (h, 1.0)] {
+                let t = (z - o.z()) / d.z();
+                let p = o + d * t;
+                if p.x() * p.x() + p.y() * p.y() <= r * r {
+                    consider(t, Vector3(0.0, 0.0, side), (0.5 + p.x() / (2.0 * r), 0.5 + p.y() / (2.0 * r)));
+                }
+            }
+        }
+
+        let (t, normal, (u, v)) = best?;
+        let outward_normal = self.frame.transform(&normal);
+        let front_face = ray.dir.dot(&outward_normal) < 0.0;
+        Some(Hit {
+            p: ray.at(t),
+            normal: if front_face {outward_normal} else {-outward_normal},
+            material: self.material.clone(),
+            t,
+            u,
+            v,
+            front_face,
+            color: None,
+        })
+    }
+
+    fn bounding(&self) -> &AABB {
+        &self.bbox
+    }
+}
@@ -0,0 +1,89 @@
+use std::{f64::consts::{PI, TAU}, sync::Arc};
+
+use rand::random;
+
+use crate::{aabb::AABB, hit::{Hit, Hittable}, interval::Interval, material::Material, onb::Onb, ray::Ray, vector::{Point3, Vector3}};
+
+pub struct Disk {
+    center: Point3,
+    radius: f64,
+    frame: Onb,
+    d: f64,
+    material: Arc<Box<dyn Material>>,
+    bbox: AABB,
+}
+
+impl Disk {
+    pub fn new(center: Point3, normal: Vector3, radius: f64, material: Arc<Box<dyn Material>>) -> Self {
+        let frame = Onb::new(&normal);
+        let bbox = disk_bounds(center, frame.w, radius).pad(1e-4);
+        Disk { center, radius, d: frame.w.dot(&center), frame, material, bbox }
+    }
+
+    pub fn as_box(self) -> Box<dyn Hittable> {
+        Box::new(self)
+    }
+}
+
+// Tight box around a circle, for anything with a round rim
+pub fn disk_bounds(center: Point3, normal: Vector3, radius: f64) -> AABB {
+    let n = normal.unit();
+    let extent = Vector3(
+        radius * (1.0 - n.x() * n.x()).max(0.0).sqrt(),
+        radius * (1.0 - n.y() * n.y()).max(0.0).sqrt(),
+        radius * (1.0 - n.z() * n.z()).max(0.0).sqrt(),
+    );
+    AABB::from(center - extent, center + extent)
+}
+
+impl Hittable for Disk {
+    fn hit(&self, ray: &Ray, ray_t: &Interval) -> Option<Hit> {
+        let normal = self.frame.w;
+        let denom = normal.dot(&ray.dir);
+        if denom.abs() < 1e-8 {return None;}
+
+        let t = (self.d - normal.dot(&ray.origin)) / denom;
+        if !ray_t.surrounds(t) {return None;}
+
+        let p = ray.at(t);
+        let offset = p - self.center;
+        if offset.length_squared() > self.radius * self.radius {return None;}
+
+        // Planar mapping, so the unit square fits just around the disk
+        let u = 0.5 + offset.dot(&self.frame.u) / (2.0 * self.radius);
+        let v = 0.5 + offset.dot(&self.frame.v) / (2.0 * self.radius);
+
+        let front_face = denom < 0.0;
+        Some(Hit {
+            p,
+            normal: if front_face {normal} else {-normal},
+            material: self.material.clone(),
+            t,
+            u,
+            v,
+            front_face,
+            color: None,
+        })
+    }
+
+    fn bounding(&self) -> &AABB {
+        &self.bbox
+    }
+
+    fn pdf_value(&self, origin: &Point3, dir: &Vector3, time: f64) -> f64 {
+        let ray = Ray {origin: *origin, dir: *dir, time};
+        let Some(hit) = self.hit(&ray, &Interval {min: 0.001, max: f64::INFINITY}) else {
+            return 0.0;
+        };
+
+        let dist_squared = hit.t * hit.t * dir.length_squared();
+        let cosine = (dir.dot(&self.frame.w) / dir.length()).abs();
+        dist_squared / (cosine * PI * self.radius * self.radius)
+    }
+
+    fn random(&self, origin: &Point3, _time: f64) -> Vector3 {
+        let r = self.radius * random::<f64>().sqrt();
+        let phi = TAU * random::<f64>();
+        self.center + self.frame.transform(&Vector3(r * phi.cos(), r * phi.sin(), 0.0)) - *origin
+    }
+}
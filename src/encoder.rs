@@ -0,0 +1,102 @@
+use std::io::Write;
+
+use crate::{color::linear_to_gamma, framebuffer::{FrameBuffer, Layer}, interval::Interval};
+
+// The first layer handed to an encoder is always the beauty pass
+pub trait ImageEncoder {
+    fn encode(&self, writer: &mut dyn Write, layers: &[Layer]);
+}
+
+fn gamma_byte(component: f64) -> u8 {
+    let intensity = Interval {min: 0.0, max: 0.999};
+    (256.0 * intensity.clamp(linear_to_gamma(component))) as u8
+}
+
+pub struct P3Encoder;
+
+impl ImageEncoder for P3Encoder {
+    fn encode(&self, writer: &mut dyn Write, layers: &[Layer]) {
+        let frame = &layers[0].buffer;
+        let mut text = format!("P3\n{} {}\n255\n", frame.width(), frame.height());
+        for color in frame.snapshot() {
+            text.push_str(&format!("{} {} {}\n", gamma_byte(color.r), gamma_byte(color.g), gamma_byte(color.b)));
+        }
+        writer.write_all(text.as_bytes()).unwrap();
+    }
+}
+
+pub struct P6Encoder;
+
+impl ImageEncoder for P6Encoder {
+    fn encode(&self, writer: &mut dyn Write, layers: &[Layer]) {
+        let frame = &layers[0].buffer;
+        writer.write_all(
+            format!("P6\n{} {}\n255\n", frame.width(), frame.height()).as_bytes()
+        ).unwrap();
+        let data: Vec<u8> = frame.snapshot().into_iter()
+            .flat_map(|color| [gamma_byte(color.r), gamma_byte(color.g), gamma_byte(color.b)])
+            .collect();
+        writer.write_all(&data).unwrap();
+    }
+}
+
+// Writes the first channel of one layer as a 16-bit binary greymap, stretched over its finite range.
+// Renderers that don't fill that layer get the beauty pass's luminance instead
+pub struct PgmEncoder {
+    pub layer: String,
+}
+
+impl PgmEncoder {
+    pub fn new(layer: &str) -> Self {
+        Self { layer: layer.to_string() }
+    }
+
+    fn range(frame: &FrameBuffer) -> Interval {
+        let mut range = Interval::EMPTY;
+        for j in 0..frame.height() {
+            for i in 0..frame.width() {
+                let value = frame.value(i, j, 0);
+                if value.is_finite() {
+                    range = Interval::enclose(&range, &Interval {min: value, max: value});
+                }
+            }
+        }
+        range
+    }
+
+    // Each pixel scaled to 0..1
+    fn values(&self, layers: &[Layer]) -> Vec<f64> {
+        let unit = Interval {min: 0.0, max: 1.0};
+        let Some(layer) = layers.iter().find(|layer| layer.name == self.layer) else {
+            return layers[0].buffer.snapshot().into_iter()
+                .map(|color| unit.clamp(linear_to_gamma(0.2126 * color.r + 0.7152 * color.g + 0.0722 * color.b)))
+                .collect();
+        };
+
+        let frame = &layer.buffer;
+        let range = Self::range(frame);
+        let mut values = Vec::with_capacity(frame.width() * frame.height());
+        for j in 0..frame.height() {
+            for i in 0..frame.width() {
+                let value = frame.value(i, j, 0);
+                let value = if range.size() > 0.0 {(value - range.min) / range.size()} else {0.0};
+                // Misses past the far end (infinite depth) come out white
+                values.push(if value.is_nan() {0.0} else {unit.clamp(value)});
+            }
+        }
+        values
+    }
+}
+
+impl ImageEncoder for PgmEncoder {
+    fn encode(&self, writer: &mut dyn Write, layers: &[Layer]) {
+        let frame = &layers[0].buffer;
+        writer.write_all(
+            format!("P5\n{} {}\n65535\n", frame.width(), frame.height()).as_bytes()
+        ).unwrap();
+        let data: Vec<u8> = self.values(layers).into_iter()
+            .flat_map(|value| ((value * 65535.0).round() as u16).to_be_bytes())
+            .collect();
+        writer.write_all(&data).unwrap();
+    }
+}
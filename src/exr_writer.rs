@@ -0,0 +1,178 @@
+use std::io::Write;
+
+use flate2::{write::ZlibEncoder, Compression};
+
+use crate::{encoder::ImageEncoder, framebuffer::Layer};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ExrCompression {
+    None,
+    Rle,
+    Zip,
+}
+
+impl ExrCompression {
+    fn id(&self) -> u8 {
+        match self {
+            ExrCompression::None => 0,
+            ExrCompression::Rle => 1,
+            ExrCompression::Zip => 3,
+        }
+    }
+
+    fn lines_per_block(&self) -> usize {
+        match self {
+            ExrCompression::None | ExrCompression::Rle => 1,
+            ExrCompression::Zip => 16,
+        }
+    }
+}
+
+// Writes every layer into one scanline image as 32-bit float channels named "layer.channel"
+pub fn write_exr<W: Write>(writer: &mut W, layers: &[Layer], compression: ExrCompression) {
+    let width = layers[0].buffer.width();
+    let height = layers[0].buffer.height();
+
+    // Channels have to be stored in alphabetical order
+    let mut channels: Vec<(String, &Layer, usize)> = layers.iter()
+        .flat_map(|layer| (0..layer.channels.len()).map(move |c| (layer.channel_name(c), layer, c)))
+        .collect();
+    channels.sort_by(|a, b| a.0.cmp(&b.0));
+
+    let mut header = Vec::new();
+    header.extend_from_slice(&20000630_i32.to_le_bytes());
+    header.extend_from_slice(&2_i32.to_le_bytes());
+
+    let mut chlist = Vec::new();
+    for (name, _, _) in &channels {
+        chlist.extend_from_slice(name.as_bytes());
+        chlist.push(0);
+        chlist.extend_from_slice(&2_i32.to_le_bytes()); // FLOAT
+        chlist.extend_from_slice(&[0, 0, 0, 0]); // pLinear + reserved
+        chlist.extend_from_slice(&1_i32.to_le_bytes());
+        chlist.extend_from_slice(&1_i32.to_le_bytes());
+    }
+    chlist.push(0);
+    attribute(&mut header, "channels", "chlist", &chlist);
+
+    attribute(&mut header, "compression", "compression", &[compression.id()]);
+
+    let mut window = Vec::new();
+    for value in [0, 0, width as i32 - 1, height as i32 - 1] {
+        window.extend_from_slice(&value.to_le_bytes());
+    }
+    attribute(&mut header, "dataWindow", "box2i", &window);
+    attribute(&mut header, "displayWindow", "box2i", &window);
+    attribute(&mut header, "lineOrder", "lineOrder", &[0]);
+    attribute(&mut header, "pixelAspectRatio", "float", &1.0_f32.to_le_bytes());
+    attribute(&mut header, "screenWindowCenter", "v2f", &[0; 8]);
+    attribute(&mut header, "screenWindowWidth", "float", &1.0_f32.to_le_bytes());
+    header.push(0);
+
+    let lines = compression.lines_per_block();
+    let mut chunks = Vec::new();
+    for y0 in (0..height).step_by(lines) {
+        let mut raw = Vec::new();
+        for j in y0..(y0 + lines).min(height) {
+            for (_, layer, channel) in &channels {
+                for i in 0..width {
+                    raw.extend_from_slice(&(layer.buffer.value(i, j, *channel) as f32).to_le_bytes());
+                }
+            }
+        }
+
+        let packed = match compression {
+            ExrCompression::None => raw,
+            ExrCompression::Rle => smallest(rle(&predict(&raw)), raw),
+            ExrCompression::Zip => {
+                let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+                encoder.write_all(&predict(&raw)).unwrap();
+                smallest(encoder.finish().unwrap(), raw)
+            },
+        };
+
+        let mut chunk = Vec::with_capacity(packed.len() + 8);
+        chunk.extend_from_slice(&(y0 as i32).to_le_bytes());
+        chunk.extend_from_slice(&(packed.len() as i32).to_le_bytes());
+        chunk.extend_from_slice(&packed);
+        chunks.push(chunk);
+    }
+
+    let mut offset = (header.len() + chunks.len() * 8) as u64;
+    for chunk in &chunks {
+        header.extend_from_slice(&offset.to_le_bytes());
+        offset += chunk.len() as u64;
+    }
+
+    writer.write_all(&header).unwrap();
+    for chunk in chunks {
+        writer.write_all(&chunk).unwrap();
+    }
+}
+
+fn attribute(header: &mut Vec<u8>, name: &str, kind: &str, value: &[u8]) {
+    header.extend_from_slice(name.as_bytes());
+    header.push(0);
+    header.extend_from_slice(kind.as_bytes());
+    header.push(0);
+    header.extend_from_slice(&(value.len() as i32).to_le_bytes());
+    header.extend_from_slice(value);
+}
+
+// Readers treat a block that didn't shrink as stored uncompressed
+fn smallest(packed: Vec<u8>, raw: Vec<u8>) -> Vec<u8> {
+    if packed.len() < raw.len() {packed} else {raw}
+}
+
+// Splits the bytes into even and odd halves, then delta encodes them, as both RLE and ZIP expect
+fn predict(raw: &[u8]) -> Vec<u8> {
+    let mut out: Vec<u8> = raw.iter().step_by(2).chain(raw.iter().skip(1).step_by(2)).copied().collect();
+    let mut prev = out.first().copied().unwrap_or(0);
+    for byte in out.iter_mut().skip(1) {
+        let cur = *byte;
+        *byte = cur.wrapping_sub(prev).wrapping_add(128);
+        prev = cur;
+    }
+    out
+}
+
+fn rle(data: &[u8]) -> Vec<u8> {
+    const MIN_RUN: usize = 3;
+    const MAX_RUN: usize = 127;
+
+    let mut out = Vec::new();
+    let mut start = 0;
+    while start < data.len() {
+        let mut end = start + 1;
+        while end < data.len() && data[end] == data[start] && end - start - 1 < MAX_RUN {
+            end += 1;
+        }
+
+        if end - start >= MIN_RUN {
+            out.push((end - start - 1) as u8);
+            out.push(data[start]);
+        } else {
+            // Copy literally until the next run of three starts
+            while end < data.len()
+                && (end + 2 >= data.len() || data[end] != data[end + 1] || data[end + 1] != data[end + 2])
+                && end - start < MAX_RUN
+            {
+                end += 1;
+            }
+            out.push((-((end - start) as i32)) as u8);
+            out.extend_from_slice(&data[start..end]);
+        }
+        start = end;
+    }
+    out
+}
+
+pub struct ExrEncoder {
+    pub compression: ExrCompression,
+}
+
+impl ImageEncoder for ExrEncoder {
+    fn encode(&self, mut writer: &mut dyn Write, layers: &[Layer]) {
+        write_exr(&mut writer, layers, self.compression);
+    }
+}
@@ -0,0 +1,123 @@
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+
+use crate::color::{Color, Pixel};
+
+// Every pixel owns its own atomics, so workers can write without taking a lock
+pub struct FrameBuffer {
+    width: usize,
+    height: usize,
+    channels: usize,
+    data: Vec<AtomicU64>,
+    written: Vec<AtomicBool>,
+    count: AtomicUsize,
+}
+
+impl FrameBuffer {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self::with_channels(width, height, 3)
+    }
+
+    pub fn with_channels(width: usize, height: usize, channels: usize) -> Self {
+        Self {
+            width,
+            height,
+            channels,
+            data: (0..(width * height * channels)).map(|_| AtomicU64::new(0)).collect(),
+            written: (0..(width * height)).map(|_| AtomicBool::new(false)).collect(),
+            count: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn write(&self, pixel: Pixel) {
+        let Color {r, g, b} = pixel.color;
+        self.write_values(pixel.i, pixel.j, &[r, g, b]);
+    }
+
+    pub fn write_values(&self, i: usize, j: usize, values: &[f64]) {
+        assert_eq!(values.len(), self.channels);
+        let index = i + j * self.width;
+        for (channel, value) in values.iter().enumerate() {
+            self.data[index * self.channels + channel].store(value.to_bits(), Ordering::Relaxed);
+        }
+        if !self.written[index].swap(true, Ordering::Release) {
+            self.count.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn value(&self, i: usize, j: usize, channel: usize) -> f64 {
+        let index = i + j * self.width;
+        f64::from_bits(self.data[index * self.channels + channel].load(Ordering::Relaxed))
+    }
+
+    pub fn get(&self, i: usize, j: usize) -> Color {
+        Color {
+            r: self.value(i, j, 0),
+            g: self.value(i, j, 1),
+            b: self.value(i, j, 2),
+        }
+    }
+
+    pub fn is_written(&self, i: usize, j: usize) -> bool {
+        self.written[i + j * self.width].load(Ordering::Acquire)
+    }
+
+    pub fn written(&self) -> usize {
+        self.count.load(Ordering::Relaxed)
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.written() == self.width * self.height
+    }
+
+    // Safe to call mid-render; pixels that haven't been written yet come back black
+    pub fn snapshot(&self) -> Vec<Color> {
+        let mut colors = Vec::with_capacity(self.width * self.height);
+        for j in 0..self.height {
+            for i in 0..self.width {
+                colors.push(if self.is_written(i, j) {self.get(i, j)} else {Color::BLACK});
+            }
+        }
+        colors
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn channels(&self) -> usize {
+        self.channels
+    }
+}
+
+// A named set of channels, e.g. "normal" with X, Y and Z. The beauty layer has an empty name
+pub struct Layer {
+    pub name: String,
+    pub channels: Vec<String>,
+    pub buffer: FrameBuffer,
+}
+
+impl Layer {
+    pub fn new(name: &str, channels: &[&str], width: usize, height: usize) -> Self {
+        Self {
+            name: name.to_string(),
+            channels: channels.iter().map(|channel| channel.to_string()).collect(),
+            buffer: FrameBuffer::with_channels(width, height, channels.len()),
+        }
+    }
+
+    pub fn beauty(width: usize, height: usize) -> Self {
+        Self::new("", &["R", "G", "B"], width, height)
+    }
+
+    pub fn channel_name(&self, channel: usize) -> String {
+        if self.name.is_empty() {
+            self.channels[channel].clone()
+        } else {
+            format!("{}.{}", self.name, self.channels[channel])
+        }
+    }
+}
@@ -0,0 +1,57 @@
+use std::io::Write;
+
+use crate::{color::Color, encoder::ImageEncoder, framebuffer::{FrameBuffer, Layer}};
+
+pub fn write_hdr<W: Write>(writer: &mut W, frame: &FrameBuffer) {
+    writer.write_all(
+        format!("#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y {} +X {}\n", frame.height(), frame.width()).as_bytes()
+    ).unwrap();
+
+    // Flat (non run-length encoded) scanlines are always valid
+    let data: Vec<u8> = frame.snapshot().into_iter().flat_map(to_rgbe).collect();
+    writer.write_all(&data).unwrap();
+}
+
+fn to_rgbe(color: Color) -> [u8; 4] {
+    let (r, g, b) = (color.r.max(0.0), color.g.max(0.0), color.b.max(0.0));
+    let v = r.max(g).max(b);
+    if v <= 0.0 || !v.is_finite() {
+        return [0, 0, 0, 0];
+    }
+
+    // v = m * 2^e with m in [0.5, 1)
+    let mut e = v.log2().floor() as i32 + 1;
+    let mut m = v / 2f64.powi(e);
+    if m >= 1.0 {
+        m /= 2.0;
+        e += 1;
+    } else if m < 0.5 {
+        m *= 2.0;
+        e -= 1;
+    }
+
+    // The exponent byte is e + 128. Anything too small for it is black, anything too big saturates
+    if e < -128 {
+        return [0, 0, 0, 0];
+    }
+    let scale = if e > 127 {
+        e = 127;
+        256.0 / 2f64.powi(e)
+    } else {
+        m * 256.0 / v
+    };
+    [
+        (r * scale) as u8,
+        (g * scale) as u8,
+        (b * scale) as u8,
+        (e + 128) as u8,
+    ]
+}
+
+pub struct HdrEncoder;
+
+impl ImageEncoder for HdrEncoder {
+    fn encode(&self, mut writer: &mut dyn Write, layers: &[Layer]) {
+        write_hdr(&mut writer, &layers[0].buffer);
+    }
+}
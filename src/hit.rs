@@ -1,6 +1,8 @@
 use std::sync::Arc;
 
-use crate::{aabb::AABB, interval::Interval, material::Material, ray::Ray, vector::{Point3, Vector3}};
+use rand::random;
+
+use crate::{aabb::AABB, bvh::BVHStats, color::Color, interval::Interval, material::Material, ray::Ray, vector::{Point3, Vector3}};
 
 pub struct Hit {
     pub p: Point3,
@@ -10,14 +12,37 @@ pub struct Hit {
     pub u: f64,
     pub v: f64,
     pub front_face: bool,
+    // Blended from the corners for meshes with vertex colors
+    pub color: Option<Color>,
 }
 
 pub trait Hittable: Send + Sync {
     fn hit(&self, ray: &Ray, ray_t: &Interval) -> Option<Hit>;
 
+    // Closest hit for each ray in a batch. BVHs trace them in SIMD packets, anything else one at a time
+    fn hit_all(&self, rays: &[Ray], ray_t: &Interval) -> Vec<Option<Hit>> {
+        rays.iter().map(|ray| self.hit(ray, ray_t)).collect()
+    }
+
     fn bounding(&self) -> &AABB;
 
     fn objects(&self) -> usize {1}
+
+    // Density, by solid angle from origin, of directions that `random` picks
+    fn pdf_value(&self, _origin: &Point3, _dir: &Vector3, _time: f64) -> f64 {0.0}
+
+    fn random(&self, _origin: &Point3, _time: f64) -> Vector3 {
+        Vector3(1.0, 0.0, 0.0)
+    }
+
+    // Only BVH nodes add to this; anything else is an object in a leaf
+    fn bvh_stats(&self, _depth: usize, _stats: &mut BVHStats) {}
+
+    // Shrinks the bounds to where the object is during `time` and returns them. Only moving objects and
+    // the things holding them need this
+    fn refit_bounds(&mut self, _time: &Interval) -> AABB {
+        *self.bounding()
+    }
 }
 
 pub struct HittableList {
@@ -73,4 +98,19 @@ impl Hittable for HittableList {
     fn objects(&self) -> usize {
         self.vec.len()
     }
+
+    fn refit_bounds(&mut self, time: &Interval) -> AABB {
+        self.bbox = self.vec.iter_mut().fold(AABB::EMPTY, |bbox, obj| AABB::enclose(&bbox, &obj.refit_bounds(time)));
+        self.bbox
+    }
+
+    fn pdf_value(&self, origin: &Point3, dir: &Vector3, time: f64) -> f64 {
+        let weight = 1.0 / self.vec.len() as f64;
+        self.vec.iter().map(|obj| weight * obj.pdf_value(origin, dir, time)).sum()
+    }
+
+    fn random(&self, origin: &Point3, time: f64) -> Vector3 {
+        let index = (random::<f64>() * self.vec.len() as f64) as usize;
+        self.vec[index.min(self.vec.len() - 1)].random(origin, time)
+    }
 }
\ No newline at end of file
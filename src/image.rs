@@ -1,13 +1,17 @@
-use std::{fs::File, sync::{Arc, Mutex}};
+use std::{fs::File, io::Read, sync::{Arc, Mutex}};
 
-use png::{Decoder, OutputInfo};
+use png::{BitDepth, ColorType, Decoder, OutputInfo, Transformations};
 
 use crate::{color::Color, writer::Debugger};
 
 pub fn get_img(file: String) -> ImgData {
-    let decoder = Decoder::new(
-        File::open(file).unwrap()
-    );
+    read_img(File::open(file).unwrap())
+}
+
+pub fn read_img(source: impl Read) -> ImgData {
+    let mut decoder = Decoder::new(source);
+    // Palettes and greys under 8 bits come out as 8-bit color or grey, 16-bit images stay 16-bit
+    decoder.set_transformations(Transformations::EXPAND);
     let mut reader = decoder.read_info().unwrap();
     let mut buf = vec![0; reader.output_buffer_size()];
     let info = reader.next_frame(&mut buf).unwrap();
@@ -21,15 +25,16 @@ pub struct ImgData {
 
 impl ImgData {
     pub fn pixel(&self, x: usize, y: usize, debugger: Arc<Mutex<Debugger>>) -> Color {
-        let index = self.deinterlace(x, y);
-        // let index = (x + y * self.height()) * 3;
-
-        let (r, g, b) =
-            (self.buf[index], self.buf[index + 1], self.buf[index + 2]);
-        let (r, g, b) = (r as f64, g as f64, b as f64);
-        let (r, g, b) = (r/256.0, g/256.0, b/256.0);
-
-        Color { r, g, b }
+        let index = self.index(x, y);
+        let sample = |k: usize| match self.info.bit_depth {
+            BitDepth::Sixteen => u16::from_be_bytes([self.buf[index + 2 * k], self.buf[index + 2 * k + 1]]) as f64 / 65536.0,
+            _ => self.buf[index + k] as f64 / 256.0,
+        };
+
+        match self.info.color_type {
+            ColorType::Grayscale | ColorType::GrayscaleAlpha => Color::from_all(sample(0)),
+            _ => Color { r: sample(0), g: sample(1), b: sample(2) },
+        }
     }
 
     pub fn width(&self) -> usize {
@@ -40,11 +45,8 @@ impl ImgData {
         self.info.height as usize
     }
 
-    fn deinterlace(&self, x: usize, y: usize) -> usize {
-        ((match y % 2 {
-            0 => x,
-            1 => (x + self.height()) % (self.height() * 2),
-            _ => unreachable!(),
-        }) + (y * self.height())) * 3
+    fn index(&self, x: usize, y: usize) -> usize {
+        let bytes = if self.info.bit_depth == BitDepth::Sixteen {2} else {1};
+        y * self.info.line_size + x * self.info.color_type.samples() * bytes
     }
 }
\ No newline at end of file
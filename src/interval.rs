@@ -8,6 +8,7 @@ pub struct Interval {
     pub max: f64,
 }
 
+#[derive(Clone, Copy)]
 pub struct SimdInterval<const N: usize>
     where LaneCount<N>: SupportedLaneCount {
     pub mins: Simd<f64, N>,
@@ -63,16 +64,57 @@ impl<const N: usize> Simdish for SimdInterval<N>
     where LaneCount<N>: SupportedLaneCount {
     type Unpacked = Interval;
     type Mask = SimdIntervalMask<N>;
-    
+    const LANES: usize = N;
+
     fn replace(self, replace: Self, mask: <Self as Simdish>::Mask) -> Self {
-        todo!()
+        SimdInterval {
+            mins: mask.mins.select(replace.mins, self.mins),
+            maxes: mask.maxes.select(replace.maxes, self.maxes),
+        }
+    }
+
+    fn lane(&self, index: usize) -> Self::Unpacked {
+        Interval {min: self.mins[index], max: self.maxes[index]}
+    }
+
+    fn from_lanes(lanes: &[Self::Unpacked]) -> Self {
+        SimdInterval {
+            mins: Simd::from_array(std::array::from_fn(|index| lanes[index].min)),
+            maxes: Simd::from_array(std::array::from_fn(|index| lanes[index].max)),
+        }
     }
 }
 
 impl<const N: usize> Maskish for SimdIntervalMask<N>
     where LaneCount<N>: SupportedLaneCount {
-    
     fn replace(self, replace: Self, mask: Self) -> Self {
-        todo!()
+        SimdIntervalMask {
+            mins: self.mins.replace(replace.mins, mask.mins),
+            maxes: self.maxes.replace(replace.maxes, mask.maxes),
+        }
     }
-}
\ No newline at end of file
+
+    fn lane(&self, index: usize) -> bool {
+        self.mins.test(index) && self.maxes.test(index)
+    }
+
+    fn from_lanes(lanes: &[bool]) -> Self {
+        let mask = Mask::from_lanes(lanes);
+        SimdIntervalMask {mins: mask, maxes: mask}
+    }
+}
+
+impl<const N: usize> Maskish for Mask<i64, N>
+    where LaneCount<N>: SupportedLaneCount {
+    fn replace(self, replace: Self, mask: Self) -> Self {
+        (replace & mask) | (self & !mask)
+    }
+
+    fn lane(&self, index: usize) -> bool {
+        self.test(index)
+    }
+
+    fn from_lanes(lanes: &[bool]) -> Self {
+        Mask::from_array(std::array::from_fn(|index| lanes[index]))
+    }
+}
@@ -0,0 +1,319 @@
+use std::time::{Duration, Instant};
+
+use threadpool::ThreadPool;
+
+use crate::{aabb::AABB, bvh::{build_index_tree, BuildNode, BVHBuilder, BVHStats, REBUILD_THRESHOLD, STACK_SIZE}, hit::{Hit, Hittable, HittableList}, interval::Interval, packet::{hit_packets, PacketNode, PacketTree, MAX_CHILDREN}, ray::Ray, vector::Vector3};
+
+// 32 bytes, so two nodes share a cache line
+#[repr(C, align(32))]
+#[derive(Clone, Copy)]
+struct LinearNode {
+    min: [f32; 3],
+    // First entry in `indices` for leaves, index of the second child for interior nodes.
+    // The first child always comes right after its parent
+    offset: u32,
+    max: [f32; 3],
+    // 0 for interior nodes
+    count: u16,
+    // The axis the children were split along
+    axis: u8,
+    _pad: u8,
+}
+
+const _: () = assert!(size_of::<LinearNode>() == 32);
+
+// Rounds outwards so the f32 box still holds everything the f64 one did
+fn round_down(x: f64) -> f32 {
+    let y = x as f32;
+    if y as f64 > x {-next_up(-y)} else {y}
+}
+
+fn round_up(x: f64) -> f32 {
+    let y = x as f32;
+    if (y as f64) < x {next_up(y)} else {y}
+}
+
+// The next f32 towards infinity. Bigger bit patterns are further from zero, so negative numbers step down
+fn next_up(y: f32) -> f32 {
+    if y.is_nan() || y == f32::INFINITY {
+        y
+    } else if y == 0.0 {
+        f32::from_bits(1)
+    } else if y > 0.0 {
+        f32::from_bits(y.to_bits() + 1)
+    } else {
+        f32::from_bits(y.to_bits() - 1)
+    }
+}
+
+impl LinearNode {
+    fn new(bbox: &AABB, offset: usize, count: usize, axis: usize) -> Self {
+        LinearNode {
+            min: [round_down(bbox.x.min), round_down(bbox.y.min), round_down(bbox.z.min)],
+            offset: offset as u32,
+            max: [round_up(bbox.x.max), round_up(bbox.y.max), round_up(bbox.z.max)],
+            count: count as u16,
+            axis: axis as u8,
+            _pad: 0,
+        }
+    }
+
+    fn bbox(&self) -> AABB {
+        let [x0, y0, z0] = self.min.map(|v| v as f64);
+        let [x1, y1, z1] = self.max.map(|v| v as f64);
+        AABB::from(Vector3(x0, y0, z0), Vector3(x1, y1, z1))
+    }
+
+    fn test(&self, origin: &[f64; 3], inv_dir: &[f64; 3], range: &Interval) -> bool {
+        let (mut min, mut max) = (range.min, range.max);
+        for axis in 0..3 {
+            let t0 = (self.min[axis] as f64 - origin[axis]) * inv_dir[axis];
+            let t1 = (self.max[axis] as f64 - origin[axis]) * inv_dir[axis];
+            let (near, far) = if t0 < t1 {(t0, t1)} else {(t1, t0)};
+            min = min.max(near);
+            max = max.min(far);
+            if max <= min {return false;}
+        }
+        true
+    }
+}
+
+// The same tree BVHNode builds, flattened into one array in depth first order. Leaves point into
+// `indices`, which point into `objects`
+pub struct LinearBVH {
+    objects: Vec<Box<dyn Hittable>>,
+    indices: Vec<u32>,
+    nodes: Vec<LinearNode>,
+    // Planes and the like, tested before the tree
+    unbounded: HittableList,
+    bbox: AABB,
+    builder: BVHBuilder,
+    // Kept from new_parallel, so rebuilds by refit are parallel too
+    threadpool: Option<ThreadPool>,
+    build_time: Duration,
+    // SAH cost right after the last build, which refit compares against
+    cost: f64,
+}
+
+impl LinearBVH {
+    pub fn new(objects: Vec<Box<dyn Hittable>>, builder: BVHBuilder) -> Self {
+        Self::build(objects, builder, None)
+    }
+
+    // Same tree as new, with big subtrees built on the threadpool
+    pub fn new_parallel(objects: Vec<Box<dyn Hittable>>, builder: BVHBuilder, threadpool: &ThreadPool) -> Self {
+        Self::build(objects, builder, Some(threadpool))
+    }
+
+    fn build(objects: Vec<Box<dyn Hittable>>, builder: BVHBuilder, threadpool: Option<&ThreadPool>) -> Self {
+        let begin = Instant::now();
+
+        let (objects, unbounded_objects): (Vec<_>, Vec<_>) = objects.into_iter()
+            .partition(|object| object.bounding().is_bounded());
+        let mut unbounded = HittableList::new();
+        for object in unbounded_objects {
+            unbounded.add(object);
+        }
+
+        let mut bvh = LinearBVH {
+            indices: Vec::with_capacity(objects.len()),
+            nodes: Vec::with_capacity(2 * objects.len()),
+            objects,
+            bbox: *unbounded.bounding(),
+            unbounded,
+            builder,
+            threadpool: threadpool.cloned(),
+            build_time: Duration::ZERO,
+            cost: 0.0,
+        };
+        if !bvh.objects.is_empty() {
+            let tree = build_index_tree(bvh.objects.iter().map(|object| *object.bounding()).collect(), builder, threadpool);
+            bvh.flatten(tree);
+            bvh.bbox = AABB::enclose(&bvh.bbox, &bvh.nodes[0].bbox());
+        }
+        bvh.build_time = begin.elapsed();
+        bvh.cost = BVHStats::of(&bvh, builder.cost_ratio()).sah_cost();
+        bvh
+    }
+
+    // Fits every box to `time`, e.g. one frame's shutter, keeping the tree as it is. If that makes the tree
+    // too slow, it's built again around where the objects are now
+    pub fn refit(&mut self, time: &Interval) {
+        let bounds: Vec<AABB> = self.objects.iter_mut().map(|object| object.refit_bounds(time)).collect();
+        self.bbox = self.unbounded.refit_bounds(time);
+        if self.nodes.is_empty() {return;}
+
+        // Children always come after their parent, so going backwards refits them first
+        for index in (0..self.nodes.len()).rev() {
+            let node = self.nodes[index];
+            let bbox = if node.count > 0 {
+                let start = node.offset as usize;
+                self.indices[start..(start + node.count as usize)].iter()
+                    .fold(AABB::EMPTY, |bbox, &i| AABB::enclose(&bbox, &bounds[i as usize]))
+            } else {
+                AABB::enclose(&self.nodes[index + 1].bbox(), &self.nodes[node.offset as usize].bbox())
+            };
+            self.nodes[index] = LinearNode::new(&bbox, node.offset as usize, node.count as usize, node.axis as usize);
+        }
+        self.bbox = AABB::enclose(&self.bbox, &self.nodes[0].bbox());
+
+        if BVHStats::of(self, self.builder.cost_ratio()).sah_cost() > self.cost * REBUILD_THRESHOLD {
+            let mut objects = std::mem::take(&mut self.objects);
+            objects.extend(std::mem::replace(&mut self.unbounded, HittableList::new()).objects());
+            let threadpool = self.threadpool.take();
+            *self = Self::build(objects, self.builder, threadpool.as_ref());
+        }
+    }
+
+    fn flatten(&mut self, tree: BuildNode<u32>) -> usize {
+        let index = self.nodes.len();
+        let (bbox, left, right) = match tree {
+            BuildNode::Leaf {bbox, items} => {
+                if items.len() > u16::MAX as usize {
+                    panic!("A BVH leaf can't hold {} objects!", items.len());
+                }
+                self.nodes.push(LinearNode::new(&bbox, self.indices.len(), items.len(), 0));
+                self.indices.extend(items);
+                return index;
+            },
+            BuildNode::Interior {bbox, left, right} => (bbox, left, right),
+        };
+
+        // Traversal visits the child on the ray's side of the axis the children are furthest apart on
+        let gap = right.bbox().center() - left.bbox().center();
+        let gaps = [gap.x().abs(), gap.y().abs(), gap.z().abs()];
+        let axis = (0..3).max_by(|&a, &b| gaps[a].partial_cmp(&gaps[b]).unwrap()).unwrap();
+        let left_first = [gap.x(), gap.y(), gap.z()][axis] >= 0.0;
+        let (first, second) = if left_first {(left, right)} else {(right, left)};
+
+        self.nodes.push(LinearNode::new(&bbox, 0, 0, axis));
+        self.flatten(*first);
+        let second = self.flatten(*second);
+        self.nodes[index].offset = second as u32;
+        index
+    }
+
+    fn packet_node(&self, index: usize) -> PacketNode {
+        let node = &self.nodes[index];
+        if node.count > 0 {
+            PacketNode::Leaf(node.offset as usize, node.count as usize)
+        } else {
+            PacketNode::Interior(index)
+        }
+    }
+
+    fn node_stats(&self, index: usize, depth: usize, stats: &mut BVHStats) {
+        let node = &self.nodes[index];
+        let area = node.bbox().surface_area();
+        stats.nodes += 1;
+        stats.max_depth = stats.max_depth.max(depth);
+        stats.root_area = stats.root_area.max(area);
+
+        if node.count > 0 {
+            stats.leaves += 1;
+            stats.leaf_objects += node.count as usize;
+            stats.leaf_area += area * node.count as f64;
+        } else {
+            stats.interior_area += area;
+            self.node_stats(index + 1, depth + 1, stats);
+            self.node_stats(node.offset as usize, depth + 1, stats);
+        }
+    }
+}
+
+impl Hittable for LinearBVH {
+    fn hit(&self, ray: &Ray, ray_t: &Interval) -> Option<Hit> {
+        let mut closest = self.unbounded.hit(ray, ray_t);
+        let mut range = Interval {min: ray_t.min, max: closest.as_ref().map_or(ray_t.max, |hit| hit.t)};
+        if self.nodes.is_empty() {return closest;}
+
+        let origin = [ray.origin.x(), ray.origin.y(), ray.origin.z()];
+        let inv_dir = [1.0 / ray.dir.x(), 1.0 / ray.dir.y(), 1.0 / ray.dir.z()];
+        let negative = inv_dir.map(|d| d < 0.0);
+
+        let mut stack = [0_u32; STACK_SIZE];
+        let mut depth = 0;
+        let mut index = 0;
+        loop {
+            let node = &self.nodes[index];
+            if node.test(&origin, &inv_dir, &range) {
+                if node.count > 0 {
+                    let start = node.offset as usize;
+                    for &i in &self.indices[start..(start + node.count as usize)] {
+                        if let Some(hit) = self.objects[i as usize].hit(ray, &range) {
+                            range.max = hit.t;
+                            closest = Some(hit);
+                        }
+                    }
+                } else {
+                    // The first child is on the low side of the axis, so rays going the other way start with the second
+                    let (near, far) = if negative[node.axis as usize] {
+                        (node.offset as usize, index + 1)
+                    } else {
+                        (index + 1, node.offset as usize)
+                    };
+                    stack[depth] = far as u32;
+                    depth += 1;
+                    index = near;
+                    continue;
+                }
+            }
+
+            if depth == 0 {break;}
+            depth -= 1;
+            index = stack[depth] as usize;
+        }
+        closest
+    }
+
+    fn hit_all(&self, rays: &[Ray], ray_t: &Interval) -> Vec<Option<Hit>> {
+        hit_packets(self, rays, ray_t)
+    }
+
+    fn bounding(&self) -> &AABB {
+        &self.bbox
+    }
+
+    fn objects(&self) -> usize {
+        self.objects.iter().map(|object| object.objects()).sum::<usize>() + Hittable::objects(&self.unbounded)
+    }
+
+    fn refit_bounds(&mut self, time: &Interval) -> AABB {
+        self.refit(time);
+        self.bbox
+    }
+
+    fn bvh_stats(&self, depth: usize, stats: &mut BVHStats) {
+        stats.build_time += self.build_time;
+        if !self.nodes.is_empty() {
+            self.node_stats(0, depth, stats);
+        }
+    }
+}
+
+impl PacketTree for LinearBVH {
+    fn root(&self) -> Option<(AABB, PacketNode)> {
+        (!self.nodes.is_empty()).then(|| (self.nodes[0].bbox(), self.packet_node(0)))
+    }
+
+    fn children(&self, node: usize, dir: &Vector3, children: &mut [(AABB, PacketNode); MAX_CHILDREN]) -> usize {
+        let second = self.nodes[node].offset as usize;
+        let order = if [dir.x(), dir.y(), dir.z()][self.nodes[node].axis as usize] < 0.0 {
+            [second, node + 1]
+        } else {
+            [node + 1, second]
+        };
+        for (slot, index) in order.into_iter().enumerate() {
+            children[slot] = (self.nodes[index].bbox(), self.packet_node(index));
+        }
+        order.len()
+    }
+
+    fn leaf_object(&self, entry: usize) -> &dyn Hittable {
+        &*self.objects[self.indices[entry] as usize]
+    }
+
+    fn unbounded(&self) -> &HittableList {
+        &self.unbounded
+    }
+}
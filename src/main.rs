@@ -1,4 +1,5 @@
 #![feature(portable_simd)]
+#![cfg_attr(test, feature(test))]
 
 mod vector;
 mod ray;
@@ -23,47 +24,114 @@ mod debug;
 mod renderer;
 mod quality;
 mod simd;
-mod sieve;
+mod tile;
+mod framebuffer;
+mod png_writer;
+mod hdr_writer;
+mod pfm_writer;
+mod exr_writer;
+mod encoder;
+mod background;
+mod onb;
+mod triangle;
+mod obj;
+mod mesh;
+mod ply;
+mod stl;
+mod triangle_mesh;
+mod quad;
+mod disk;
+mod plane;
+mod cylinder;
+mod cone;
+mod capsule;
+mod torus;
+mod matrix;
+mod transform;
+mod tlas;
+mod linear_bvh;
+mod bvh4;
+mod packet;
 
 #[cfg(test)]
 mod tests;
+#[cfg(test)]
+mod benches;
 
-use std::time::Instant;
+use std::{path::Path, sync::Arc, time::Instant};
 
 use anim::Animation;
-use bvh::BVHNode;
+use background::Background;
+use bvh::{BVHBuilder, BVHStats, SahOptions};
+use bvh4::Bvh4;
 use camera::Camera;
+use capsule::Capsule;
 use color::Color;
-use hit::HittableList;
-use material::{Dielectric, Lambertian, Metal};
+use cone::Cone;
+use cylinder::Cylinder;
+use disk::Disk;
+use hit::{Hittable, HittableList};
+use interval::Interval;
+use material::{Dielectric, DiffuseLight, Lambertian, Metal};
+use matrix::Mat4;
+use obj::load_obj;
+use plane::Plane;
+use ply::load_ply;
+use quad::{make_box, Quad};
 use quality::QualityOptions;
 use rand::random;
 use renderer::{DefaultRenderer, ScreenUV, UV};
 use scene::Scene;
 use sphere::Sphere;
+use stl::load_stl;
 use texture::{CheckerTexture, ImageTexture};
+use tlas::Tlas;
+use tile::{TileOptions, TileOrder};
+use torus::Torus;
+use transform::Transformed;
+use triangle::Triangle;
+use triangle_mesh::TriangleMesh;
 use vector::Vector3;
+use writer::OutputFormat;
 
 const SCENE_ID: usize = 0;
 
 const RENDERER: usize = 0;
 
-const FILE_OUT: &str = "out.ppm";
+const FILE_OUT: &str = "out.png";
+
+// Leave as None to pick the format from FILE_OUT's extension. A .ppm is binary P6; use OutputFormat::PpmAscii for text P3
+const OUTPUT_FORMAT: Option<OutputFormat> = None;
+
+const TILES: TileOptions = TileOptions::new(32, TileOrder::Spiral);
+
+const SAH: SahOptions = SahOptions::new(16, 4, 1.0);
+
+// The part of the animation one frame covers, for motion blur
+const SHUTTER: Interval = Interval {min: 0.0, max: 1.0};
+
+// BVHBuilder::Median sorts and splits in the middle instead
+const BVH_BUILDER: BVHBuilder = BVHBuilder::Sah(SAH);
 
 fn main() {    
     print!("Generating Scene...  ");
 
-    let scene = match SCENE_ID {
+    let mut scene = match SCENE_ID {
         0 => bouncing_spheres(),
         1 => checkered_spheres(),
         2 => earth(),
+        3 => simple_light(),
+        4 => obj_model(),
+        5 => cornell_box(),
+        6 => shapes(),
+        7 => instances(),
         _ => panic!("That Scene ID does not exist!"),
     };
 
     let renderer = match RENDERER {
-        0 => DefaultRenderer::new(),
-        1 => ScreenUV::new(),
-        2 => UV::new(),
+        0 => DefaultRenderer::new(TILES),
+        1 => ScreenUV::new(TILES),
+        2 => UV::new(TILES),
         _ => panic!("That renderer ID does not exist!"),
     };
 
@@ -77,6 +145,8 @@ fn main() {
         scene.camera.total_pixels()/1000,
         FILE_OUT,
     );
+    scene.refit();
+    println!("BVH: {}", BVHStats::of(&*scene.root, SAH.cost_ratio));
     
     // print!("This operation is expected to take {}", get_time_str(scene.est_time()));
 
@@ -115,7 +185,7 @@ fn bouncing_spheres() -> Scene {
         0.6,
         10.0,
         8,
-    );
+    ).with_shutter(SHUTTER);
 
     let mut world = HittableList::new();
 
@@ -125,11 +195,9 @@ fn bouncing_spheres() -> Scene {
             Color { r: 0.2, g: 0.3, b: 0.1 },
             Color { r: 0.9, g: 0.9, b: 0.9 },
     ).to_box();
-    world.add(Sphere::from_const_pos(
-        0.0,
-        -1000.0,
-        0.0,
-        1000.0,
+    world.add(Plane::new(
+        Vector3(0.0, 0.0, 0.0),
+        Vector3(0.0, 1.0, 0.0),
         Lambertian::new(checker).to_dyn()
     ).as_box());
     
@@ -149,7 +217,7 @@ fn bouncing_spheres() -> Scene {
                     let albedo = Color::random() * Color::random();
                     let material = Lambertian::from_const_col(albedo).to_dyn();
                     let pos_2 =
-                        center + Vector3::from(0.0, 0.0, 0.0);
+                        center + Vector3::from(0.0, random::<f64>() * 0.5, 0.0);
                     world.add(Sphere::new(
                         Animation::linear(vec![center, pos_2], 0.2),
                         0.2, material
@@ -183,7 +251,7 @@ fn bouncing_spheres() -> Scene {
     let material = Metal::from(0.7, 0.6, 0.5, 0.0).to_dyn();
     world.add(Sphere::from_const_pos(4.0, 1.0, 0.0, 1.0, material).as_box());
 
-    let bvh = BVHNode::new(world.objects(), "debug.txt");
+    let bvh = Bvh4::new_parallel(world.objects(), BVH_BUILDER, &camera.threadpool);
 
     Scene::new(camera, bvh)
 }
@@ -220,7 +288,7 @@ fn checkered_spheres() -> Scene {
         0.0, 10.0, 0.0, 10.0, checker.clone()
     ).as_box());
     
-    let root = BVHNode::new(world.objects(), "debug.txt");
+    let root = Bvh4::new_parallel(world.objects(), BVH_BUILDER, &camera.threadpool);
 
     Scene::new(camera, root)
 }
@@ -237,7 +305,154 @@ fn earth() -> Scene {
         0.0, 0.0, 0.0, 2.0, earth_surface
     ).as_box();
 
-    Scene::new(camera, BVHNode::new(vec![globe], "debug.txt"))
+    let root = Bvh4::new_parallel(vec![globe], BVH_BUILDER, &camera.threadpool);
+
+    Scene::new(camera, root)
+}
+
+fn simple_light() -> Scene {
+    let camera = Camera::new(QualityOptions::DEFAULT, 16.0/9.0, Vector3(26.0, 3.0, 6.0), 20.0, Vector3(0.0, 2.0, 0.0), Vector3(0.0, 1.0, 0.0), 0.0, 10.0, 8);
+
+    let mut world = HittableList::new();
+    let checker = Lambertian::new(
+        CheckerTexture::from_const_col(
+            0.32,
+            Color { r: 0.2, g: 0.3, b: 0.1 },
+            Color { r: 0.9, g: 0.9, b: 0.9 },
+        ).to_box()
+    ).to_dyn();
+    world.add(Plane::new(Vector3(0.0, 0.0, 0.0), Vector3(0.0, 1.0, 0.0), checker.clone()).as_box());
+    world.add(Sphere::from_const_pos(0.0, 2.0, 0.0, 2.0, checker).as_box());
+
+    let light = DiffuseLight::from_const_col(Color::from_all(4.0)).to_dyn();
+    world.add(Sphere::from_const_pos(0.0, 7.0, 0.0, 2.0, light.clone()).as_box());
+
+    let mut lights = HittableList::new();
+    lights.add(Sphere::from_const_pos(0.0, 7.0, 0.0, 2.0, light).as_box());
+
+    let root = Bvh4::new_parallel(world.objects(), BVH_BUILDER, &camera.threadpool);
+
+    Scene::new(camera, root)
+        .with_background(Background::Solid(Color::BLACK))
+        .with_lights(lights)
+}
+
+fn obj_model() -> Scene {
+    let camera = Camera::new(QualityOptions::DEFAULT, 16.0/9.0, Vector3(6.0, 4.0, 8.0), 30.0, Vector3(0.0, 0.0, 0.0), Vector3(0.0, 1.0, 0.0), 0.0, 10.0, 8);
+
+    let mut world = HittableList::new();
+    world.add(load_model("models/cube.obj").as_box());
+    let ground = Lambertian::from_const_col(Color::from_all(0.5)).to_dyn();
+    world.add(Sphere::from_const_pos(0.0, -1001.0, 0.0, 1000.0, ground).as_box());
+
+    let root = Bvh4::new_parallel(world.objects(), BVH_BUILDER, &camera.threadpool);
+
+    Scene::new(camera, root)
+}
+
+fn cornell_box() -> Scene {
+    let camera = Camera::new(QualityOptions::DEFAULT, 1.0, Vector3(278.0, 278.0, -800.0), 40.0, Vector3(278.0, 278.0, 0.0), Vector3(0.0, 1.0, 0.0), 0.0, 10.0, 8);
+
+    let red = Lambertian::from_const_col(Color {r: 0.65, g: 0.05, b: 0.05}).to_dyn();
+    let white = Lambertian::from_const_col(Color::from_all(0.73)).to_dyn();
+    let green = Lambertian::from_const_col(Color {r: 0.12, g: 0.45, b: 0.15}).to_dyn();
+    let light = DiffuseLight::from_const_col(Color::from_all(15.0)).to_dyn();
+
+    let mut world = HittableList::new();
+    world.add(Quad::new(Vector3(555.0, 0.0, 0.0), Vector3(0.0, 555.0, 0.0), Vector3(0.0, 0.0, 555.0), green).as_box());
+    world.add(Quad::new(Vector3(0.0, 0.0, 0.0), Vector3(0.0, 555.0, 0.0), Vector3(0.0, 0.0, 555.0), red).as_box());
+    world.add(Quad::new(Vector3(0.0, 0.0, 0.0), Vector3(555.0, 0.0, 0.0), Vector3(0.0, 0.0, 555.0), white.clone()).as_box());
+    world.add(Quad::new(Vector3(555.0, 555.0, 555.0), Vector3(-555.0, 0.0, 0.0), Vector3(0.0, 0.0, -555.0), white.clone()).as_box());
+    world.add(Quad::new(Vector3(0.0, 0.0, 555.0), Vector3(555.0, 0.0, 0.0), Vector3(0.0, 555.0, 0.0), white.clone()).as_box());
+
+    let lamp = || Disk::new(Vector3(278.0, 554.0, 278.0), Vector3(0.0, -1.0, 0.0), 80.0, light.clone());
+    world.add(lamp().as_box());
+
+    for object in make_box(Vector3(265.0, 0.0, 295.0), Vector3(430.0, 330.0, 460.0), white.clone()).objects() {
+        world.add(object);
+    }
+    let metal = Metal::from(0.8, 0.85, 0.88, 0.0).to_dyn();
+    world.add(Sphere::from_const_pos(190.0, 90.0, 190.0, 90.0, metal).as_box());
+
+    let mut lights = HittableList::new();
+    lights.add(lamp().as_box());
+
+    let root = Bvh4::new_parallel(world.objects(), BVH_BUILDER, &camera.threadpool);
+
+    Scene::new(camera, root)
+        .with_background(Background::Solid(Color::BLACK))
+        .with_lights(lights)
+}
+
+fn shapes() -> Scene {
+    let camera = Camera::new(QualityOptions::DEFAULT, 16.0/9.0, Vector3(0.0, 4.0, 12.0), 35.0, Vector3(0.0, 1.0, 0.0), Vector3(0.0, 1.0, 0.0), 0.0, 10.0, 8);
+
+    let checker = Lambertian::new(
+        CheckerTexture::from_const_col(
+            0.5,
+            Color { r: 0.2, g: 0.3, b: 0.1 },
+            Color { r: 0.9, g: 0.9, b: 0.9 },
+        ).to_box()
+    ).to_dyn();
+    let earth = Lambertian::new(ImageTexture::new("img/map.png".to_string()).to_box()).to_dyn();
+    let copper = Metal::from(0.8, 0.5, 0.3, 0.1).to_dyn();
+    let blue = Lambertian::from_const_col(Color {r: 0.1, g: 0.2, b: 0.6}).to_dyn();
+    let glass = Dielectric::from(1.5).to_dyn();
+    let red = Lambertian::from_const_col(Color {r: 0.7, g: 0.15, b: 0.1}).to_dyn();
+
+    let mut world = HittableList::new();
+    world.add(Plane::new(Vector3(0.0, 0.0, 0.0), Vector3(0.0, 1.0, 0.0), checker).as_box());
+    world.add(Triangle::new(Vector3(-3.0, 0.0, -4.0), Vector3(3.0, 0.0, -4.0), Vector3(0.0, 4.0, -4.0), red).as_box());
+    world.add(Cylinder::new(Vector3(-4.5, 0.0, 0.0), Vector3(-4.5, 2.5, 0.0), 1.0, earth).as_box());
+    world.add(Cone::new(Vector3(-1.5, 0.0, 0.0), Vector3(-1.5, 2.5, 0.0), 1.0, blue).as_box());
+    world.add(Capsule::new(Vector3(0.8, 0.6, -0.5), Vector3(2.0, 2.2, 0.5), 0.6, glass).as_box());
+    world.add(Torus::new(Vector3(4.5, 1.3, 0.0), Vector3(0.0, 1.0, 1.0), 1.0, 0.35, copper).as_box());
+
+    let root = Bvh4::new_parallel(world.objects(), BVH_BUILDER, &camera.threadpool);
+
+    Scene::new(camera, root)
+}
+
+fn instances() -> Scene {
+    let camera = Camera::new(QualityOptions::DEFAULT, 16.0/9.0, Vector3(0.0, 14.0, 22.0), 35.0, Vector3(0.0, 0.0, 0.0), Vector3(0.0, 1.0, 0.0), 0.0, 10.0, 8);
+
+    let ground = Lambertian::from_const_col(Color::from_all(0.5)).to_dyn();
+    let mut world = HittableList::new();
+    world.add(Plane::new(Vector3(0.0, -1.0, 0.0), Vector3(0.0, 1.0, 0.0), ground).as_box());
+
+    // Every copy shares this one tree, and the top level tree only sees the copies
+    let cube: Arc<Box<dyn Hittable>> = Arc::new(load_model("models/cube.obj").as_box());
+    let mut instances = Vec::new();
+    for a in -5..=5 {
+        for b in -3..=3 {
+            let size = 0.3 + 0.25 * random::<f64>();
+            let matrix = Mat4::translate(Vector3(a as f64 * 1.6, size - 1.0, b as f64 * 1.6))
+                * Mat4::rotate(Vector3(0.0, 1.0, 0.0), 90.0 * random::<f64>())
+                * Mat4::scale(Vector3::from_all(size));
+            instances.push(Transformed::new(cube.clone(), matrix));
+        }
+    }
+    world.add(Tlas::new(instances).as_box());
+
+    let root = Bvh4::new_parallel(world.objects(), BVH_BUILDER, &camera.threadpool);
+
+    Scene::new(camera, root)
+}
+
+// Picks the loader from the file extension. Meshes without materials of their own are plain grey
+fn load_model(path: &str) -> TriangleMesh {
+    let material = Lambertian::from_const_col(Color::from_all(0.8)).to_dyn();
+    match Path::new(path).extension().and_then(|extension| extension.to_str()) {
+        Some("obj") => load_obj(path).map(|(model, warnings)| {
+            for warning in warnings {
+                eprintln!("{}", warning);
+            }
+            model
+        }),
+        Some("ply") => load_ply(path, material),
+        Some("stl") => load_stl(path, material),
+        _ => Err(String::from("Unknown file type")),
+    }.unwrap_or_else(|err| panic!("Couldn't load {}: {}", path, err))
 }
 
 fn get_time_str(seconds: usize) -> String {
@@ -1,9 +1,42 @@
-use std::sync::{Arc, Mutex};
+use std::{f64::consts::PI, sync::{Arc, Mutex}};
 
-use crate::{color::Color, hit::{Hit, Hittable}, ray::Ray, texture::{SolidTexture, Texture}, vector::Vector3, writer::Debugger};
+use crate::{color::Color, hit::{Hit, Hittable}, onb::Onb, ray::Ray, texture::{SolidTexture, Texture}, vector::{Point3, Vector3}, writer::Debugger};
+
+pub struct ScatterRecord {
+    // The BSDF times cosine over `pdf`, i.e. the throughput of the sampled ray
+    pub attenuation: Color,
+    pub scattered: Ray,
+    pub pdf: f64,
+    // Delta distributions can't be evaluated for other directions, so `pdf` is meaningless
+    pub specular: bool,
+}
+
+impl ScatterRecord {
+    pub fn specular(attenuation: Color, scattered: Ray) -> Self {
+        ScatterRecord { attenuation, scattered, pdf: 0.0, specular: true }
+    }
+}
 
 pub trait Material: Send + Sync {
-    fn scatter(&self, r_in: &Ray, hit: &Hit, debugger: Arc<Mutex<Debugger>>) -> Option<(Color, Ray)>;
+    fn scatter(&self, r_in: &Ray, hit: &Hit, debugger: Arc<Mutex<Debugger>>) -> Option<ScatterRecord>;
+
+    // BSDF times cosine for light leaving along `scattered`. Always black for specular materials
+    fn eval(&self, _r_in: &Ray, _hit: &Hit, _scattered: &Ray, _debugger: Arc<Mutex<Debugger>>) -> Color {
+        Color::BLACK
+    }
+
+    // Density `scatter` would pick `scattered` with
+    fn pdf(&self, _r_in: &Ray, _hit: &Hit, _scattered: &Ray) -> f64 {
+        0.0
+    }
+
+    fn albedo(&self, _hit: &Hit, _debugger: Arc<Mutex<Debugger>>) -> Color {
+        Color::BLACK
+    }
+
+    fn emitted(&self, _u: f64, _v: f64, _p: &Point3, _debugger: Arc<Mutex<Debugger>>) -> Color {
+        Color::BLACK
+    }
 }
 
 pub struct Lambertian {
@@ -29,12 +62,27 @@ impl Lambertian {
 }
 
 impl Material for Lambertian {
-    fn scatter(&self, r_in: &Ray, hit: &Hit, debugger: Arc<Mutex<Debugger>>) -> Option<(Color, Ray)> {
-        let mut dir = hit.normal + Vector3::random_unit();
-        if dir.is_near_zero() {dir = hit.normal};
-        let scatter = Ray {origin: hit.p, dir, time: r_in.time};
-        let attenuation = self.texture.value(hit.u, hit.v, &hit.p, debugger);
-        Some((attenuation, scatter))
+    fn scatter(&self, r_in: &Ray, hit: &Hit, debugger: Arc<Mutex<Debugger>>) -> Option<ScatterRecord> {
+        let dir = Onb::new(&hit.normal).transform(&Vector3::random_cosine_direction());
+        let scattered = Ray {origin: hit.p, dir, time: r_in.time};
+        let pdf = self.pdf(r_in, hit, &scattered);
+        if pdf <= 0.0 {return None;}
+        // Cosine sampling cancels out everything but the albedo
+        let attenuation = self.texture.value_at(hit, debugger);
+        Some(ScatterRecord { attenuation, scattered, pdf, specular: false })
+    }
+
+    fn eval(&self, r_in: &Ray, hit: &Hit, scattered: &Ray, debugger: Arc<Mutex<Debugger>>) -> Color {
+        self.texture.value_at(hit, debugger) * self.pdf(r_in, hit, scattered)
+    }
+
+    fn pdf(&self, _r_in: &Ray, hit: &Hit, scattered: &Ray) -> f64 {
+        let cos_theta = hit.normal.dot(&scattered.dir.unit());
+        if cos_theta < 0.0 {0.0} else {cos_theta / PI}
+    }
+
+    fn albedo(&self, hit: &Hit, debugger: Arc<Mutex<Debugger>>) -> Color {
+        self.texture.value_at(hit, debugger)
     }
 }
 
@@ -54,17 +102,21 @@ impl Metal {
 }
 
 impl Material for Metal {
-    fn scatter(&self, r_in: &Ray, hit: &Hit, _debugger: Arc<Mutex<Debugger>>) -> Option<(Color, Ray)> {
+    fn scatter(&self, r_in: &Ray, hit: &Hit, _debugger: Arc<Mutex<Debugger>>) -> Option<ScatterRecord> {
         let dir = r_in.dir.reflect(&hit.normal);
         let dir = dir.unit() + (Vector3::random_unit() * self.fuzz);
         let scatter = Ray {origin: hit.p, dir, time: r_in.time};
         let attenuation = self.albedo;
         if scatter.dir.dot(&hit.normal) > 0.0 {
-            Some((attenuation, scatter))
+            Some(ScatterRecord::specular(attenuation, scatter))
         } else {
             None
         }
     }
+
+    fn albedo(&self, _hit: &Hit, _debugger: Arc<Mutex<Debugger>>) -> Color {
+        self.albedo
+    }
 }
 
 pub struct Dielectric {
@@ -88,7 +140,7 @@ impl Dielectric {
 }
 
 impl Material for Dielectric {
-    fn scatter(&self, r_in: &Ray, hit: &Hit, debugger: Arc<Mutex<Debugger>>) -> Option<(Color, Ray)> {
+    fn scatter(&self, r_in: &Ray, hit: &Hit, _debugger: Arc<Mutex<Debugger>>) -> Option<ScatterRecord> {
         let ri = if hit.front_face {1.0/self.index} else {self.index};
         let unit_dir = r_in.dir.unit();
         let cos_theta = -unit_dir.dot(&hit.normal).min(1.0);
@@ -102,7 +154,43 @@ impl Material for Dielectric {
             unit_dir.refract(&hit.normal, ri)
         };
         let scattered = Ray { origin: hit.p, dir, time: r_in.time };
-        Some((Color::WHITE, scattered))
+        Some(ScatterRecord::specular(Color::WHITE, scattered))
+    }
+
+    fn albedo(&self, _hit: &Hit, _debugger: Arc<Mutex<Debugger>>) -> Color {
+        Color::WHITE
+    }
+}
+
+pub struct DiffuseLight {
+    texture: Box<dyn Texture>,
+}
+
+impl DiffuseLight {
+    pub fn new(texture: Box<dyn Texture>) -> Self {
+        Self {
+            texture,
+        }
+    }
+
+    pub fn from_const_col(emit: Color) -> Self {
+        Self {
+            texture: SolidTexture::new(emit).to_box()
+        }
+    }
+
+    pub fn to_dyn(self) -> Arc<Box<dyn Material>> {
+        Arc::new(Box::new(self))
+    }
+}
+
+impl Material for DiffuseLight {
+    fn scatter(&self, _r_in: &Ray, _hit: &Hit, _debugger: Arc<Mutex<Debugger>>) -> Option<ScatterRecord> {
+        None
+    }
+
+    fn emitted(&self, u: f64, v: f64, p: &Point3, debugger: Arc<Mutex<Debugger>>) -> Color {
+        self.texture.value(u, v, p, debugger)
     }
 }
 
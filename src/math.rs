@@ -1,4 +1,4 @@
-use std::ops::{Add, Mul};
+use std::{f64::consts::TAU, ops::{Add, Mul}};
 
 pub fn lerp<T>(a: &T, b: &T, t: f64) -> <<T as Mul<f64>>::Output as Add>::Output     where
     T: Mul<f64> + Clone,
@@ -7,4 +7,99 @@ pub fn lerp<T>(a: &T, b: &T, t: f64) -> <<T as Mul<f64>>::Output as Add>::Output
     let a = a.clone();
     let b = b.clone();
     a * (1.0-t) + b * t
-}
\ No newline at end of file
+}
+
+// Weight for a sample taken with density `f` when `g` could also have produced it
+pub fn power_heuristic(f: f64, g: f64) -> f64 {
+    let f2 = f * f;
+    let g2 = g * g;
+    if f2.is_infinite() {return 1.0;}
+    if f2 + g2 == 0.0 {0.0} else {f2 / (f2 + g2)}
+}
+
+// Real roots of a x^2 + b x + c, smallest first
+pub fn solve_quadratic(a: f64, b: f64, c: f64) -> Vec<f64> {
+    if a == 0.0 {
+        return if b == 0.0 {Vec::new()} else {vec![-c / b]};
+    }
+    let discr = b * b - 4.0 * a * c;
+    if discr < 0.0 {return Vec::new();}
+
+    // Avoids cancellation between b and the square root
+    let q = -0.5 * (b + b.signum() * discr.sqrt());
+    let (r0, r1) = if q == 0.0 {(0.0, 0.0)} else {(q / a, c / q)};
+    if r0 < r1 {vec![r0, r1]} else {vec![r1, r0]}
+}
+
+// Real roots of x^3 + a x^2 + b x + c
+pub fn solve_cubic(a: f64, b: f64, c: f64) -> Vec<f64> {
+    let q = (a * a - 3.0 * b) / 9.0;
+    let r = (2.0 * a * a * a - 9.0 * a * b + 27.0 * c) / 54.0;
+    let shift = a / 3.0;
+
+    if r * r < q * q * q {
+        let theta = (r / (q * q * q).sqrt()).clamp(-1.0, 1.0).acos();
+        let scale = -2.0 * q.sqrt();
+        vec![
+            scale * (theta / 3.0).cos() - shift,
+            scale * ((theta + TAU) / 3.0).cos() - shift,
+            scale * ((theta - TAU) / 3.0).cos() - shift,
+        ]
+    } else {
+        let big = -r.signum() * (r.abs() + (r * r - q * q * q).sqrt()).cbrt();
+        let small = if big == 0.0 {0.0} else {q / big};
+        vec![big + small - shift]
+    }
+}
+
+// Real roots of a x^4 + b x^3 + c x^2 + d x + e by Ferrari's method, polished with Newton steps, smallest first
+pub fn solve_quartic(a: f64, b: f64, c: f64, d: f64, e: f64) -> Vec<f64> {
+    if a == 0.0 {
+        let mut roots = solve_cubic_general(b, c, d, e);
+        roots.sort_by(|x, y| x.partial_cmp(y).unwrap());
+        return roots;
+    }
+    let (b, c, d, e) = (b / a, c / a, d / a, e / a);
+
+    // Depress to y^4 + p y^2 + q y + r with x = y - b/4
+    let b2 = b * b;
+    let p = c - 3.0 * b2 / 8.0;
+    let q = d - b * c / 2.0 + b2 * b / 8.0;
+    let r = e - b * d / 4.0 + b2 * c / 16.0 - 3.0 * b2 * b2 / 256.0;
+
+    let mut ys = Vec::new();
+    if q.abs() < 1e-12 {
+        // Biquadratic
+        for z in solve_quadratic(1.0, p, r) {
+            if z >= 0.0 {
+                ys.push(z.sqrt());
+                ys.push(-z.sqrt());
+            }
+        }
+    } else {
+        // Any positive root of the resolvent cubic splits it into two quadratics
+        let m = solve_cubic(p, p * p / 4.0 - r, -q * q / 8.0).into_iter().fold(f64::NEG_INFINITY, f64::max);
+        if m > 0.0 {
+            let s = (2.0 * m).sqrt();
+            ys.extend(solve_quadratic(1.0, s, p / 2.0 + m - q / (2.0 * s)));
+            ys.extend(solve_quadratic(1.0, -s, p / 2.0 + m + q / (2.0 * s)));
+        }
+    }
+
+    let f = |x: f64| (((x + b) * x + c) * x + d) * x + e;
+    let df = |x: f64| ((4.0 * x + 3.0 * b) * x + 2.0 * c) * x + d;
+    let mut roots: Vec<f64> = ys.into_iter().map(|y| {
+        let mut x = y - b / 4.0;
+        for _ in 0..2 {
+            let slope = df(x);
+            if slope != 0.0 {x -= f(x) / slope;}
+        }
+        x
+    }).collect();
+    roots.sort_by(|x, y| x.partial_cmp(y).unwrap());
+    roots
+}
+
+fn solve_cubic_general(a: f64, b: f64, c: f64, d: f64) -> Vec<f64> {
+    if a == 0.0 {solve_quadratic(b, c, d)} else {solve_cubic(b / a, c / a, d / a)}
+}
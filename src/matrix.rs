@@ -0,0 +1,135 @@
+use std::ops::Mul;
+
+use crate::vector::{Point3, Vector3};
+
+// Row-major 4x4 matrix for affine transforms, applied to column vectors
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Mat4 {
+    pub m: [[f64; 4]; 4],
+}
+
+impl Mat4 {
+    pub const IDENTITY: Mat4 = Mat4 {
+        m: [
+            [1.0, 0.0, 0.0, 0.0],
+            [0.0, 1.0, 0.0, 0.0],
+            [0.0, 0.0, 1.0, 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ],
+    };
+
+    pub fn translate(offset: Vector3) -> Self {
+        let mut matrix = Self::IDENTITY;
+        matrix.m[0][3] = offset.x();
+        matrix.m[1][3] = offset.y();
+        matrix.m[2][3] = offset.z();
+        matrix
+    }
+
+    pub fn scale(factors: Vector3) -> Self {
+        let mut matrix = Self::IDENTITY;
+        matrix.m[0][0] = factors.x();
+        matrix.m[1][1] = factors.y();
+        matrix.m[2][2] = factors.z();
+        matrix
+    }
+
+    // Counter-clockwise around axis when looking down it, like the right hand rule
+    pub fn rotate(axis: Vector3, degrees: f64) -> Self {
+        let Vector3(x, y, z) = axis.unit();
+        let (sin, cos) = degrees.to_radians().sin_cos();
+        let k = 1.0 - cos;
+        Mat4 {
+            m: [
+                [cos + x * x * k, x * y * k - z * sin, x * z * k + y * sin, 0.0],
+                [y * x * k + z * sin, cos + y * y * k, y * z * k - x * sin, 0.0],
+                [z * x * k - y * sin, z * y * k + x * sin, cos + z * z * k, 0.0],
+                [0.0, 0.0, 0.0, 1.0],
+            ],
+        }
+    }
+
+    pub fn transform_point(&self, p: &Point3) -> Point3 {
+        let m = &self.m;
+        Vector3(
+            m[0][0] * p.x() + m[0][1] * p.y() + m[0][2] * p.z() + m[0][3],
+            m[1][0] * p.x() + m[1][1] * p.y() + m[1][2] * p.z() + m[1][3],
+            m[2][0] * p.x() + m[2][1] * p.y() + m[2][2] * p.z() + m[2][3],
+        )
+    }
+
+    // Ignores the translation, for directions
+    pub fn transform_vector(&self, v: &Vector3) -> Vector3 {
+        let m = &self.m;
+        Vector3(
+            m[0][0] * v.x() + m[0][1] * v.y() + m[0][2] * v.z(),
+            m[1][0] * v.x() + m[1][1] * v.y() + m[1][2] * v.z(),
+            m[2][0] * v.x() + m[2][1] * v.y() + m[2][2] * v.z(),
+        )
+    }
+
+    pub fn transpose(&self) -> Self {
+        let mut matrix = *self;
+        for row in 0..4 {
+            for col in 0..4 {
+                matrix.m[row][col] = self.m[col][row];
+            }
+        }
+        matrix
+    }
+
+    // Of the upper 3x3, i.e. how much the transform scales volumes
+    pub fn determinant3(&self) -> f64 {
+        let m = &self.m;
+        m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+            - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+            + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0])
+    }
+
+    // Gauss-Jordan elimination with partial pivoting
+    pub fn inverse(&self) -> Self {
+        let mut a = self.m;
+        let mut inv = Self::IDENTITY.m;
+
+        for col in 0..4 {
+            let pivot = (col..4).max_by(|&i, &j| a[i][col].abs().partial_cmp(&a[j][col].abs()).unwrap()).unwrap();
+            if a[pivot][col].abs() < 1e-12 {
+                panic!("This matrix can't be inverted!");
+            }
+            a.swap(col, pivot);
+            inv.swap(col, pivot);
+
+            let scale = 1.0 / a[col][col];
+            for k in 0..4 {
+                a[col][k] *= scale;
+                inv[col][k] *= scale;
+            }
+
+            for row in 0..4 {
+                if row == col {continue;}
+                let factor = a[row][col];
+                for k in 0..4 {
+                    a[row][k] -= factor * a[col][k];
+                    inv[row][k] -= factor * inv[col][k];
+                }
+            }
+        }
+
+        Mat4 { m: inv }
+    }
+}
+
+impl Mul for Mat4 {
+    type Output = Mat4;
+
+    // a * b applies b first
+    fn mul(self, rhs: Self) -> Self::Output {
+        let mut m = [[0.0; 4]; 4];
+        for (row, out) in m.iter_mut().enumerate() {
+            for (col, value) in out.iter_mut().enumerate() {
+                *value = (0..4).map(|k| self.m[row][k] * rhs.m[k][col]).sum();
+            }
+        }
+        Mat4 { m }
+    }
+}
@@ -0,0 +1,26 @@
+use crate::{color::Color, vector::{Point3, Vector3}};
+
+// An indexed triangle mesh as read from disk; attributes are either missing or one per position
+pub struct Mesh {
+    pub positions: Vec<Point3>,
+    pub normals: Option<Vec<Vector3>>,
+    pub uvs: Option<Vec<(f64, f64)>>,
+    pub colors: Option<Vec<Color>>,
+    pub indices: Vec<[usize; 3]>,
+}
+
+impl Mesh {
+    pub fn new() -> Self {
+        Mesh {
+            positions: Vec::new(),
+            normals: None,
+            uvs: None,
+            colors: None,
+            indices: Vec::new(),
+        }
+    }
+
+    pub fn num_triangles(&self) -> usize {
+        self.indices.len()
+    }
+}
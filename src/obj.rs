@@ -0,0 +1,265 @@
+use std::{collections::HashMap, fs::{self, File}, io::{BufRead, BufReader}, path::Path, sync::Arc};
+
+use crate::{color::Color, material::{Dielectric, Lambertian, Material, Metal}, mesh::Mesh, texture::ImageTexture, triangle_mesh::TriangleMesh, vector::{Point3, Vector3}};
+
+// One corner of a face: indices into the position, texture and normal lists
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+struct Corner {
+    v: usize,
+    vt: Option<usize>,
+    vn: Option<usize>,
+}
+
+// A group's faces as they're read. Corners with the same position, texture and normal share a vertex
+struct Group {
+    name: String,
+    mesh: Mesh,
+    vertices: HashMap<Corner, usize>,
+    uvs: Vec<Option<(f64, f64)>>,
+    normals: Vec<Option<Vector3>>,
+    face_materials: Vec<usize>,
+}
+
+impl Group {
+    fn new(name: String) -> Self {
+        Group {
+            name,
+            mesh: Mesh::new(),
+            vertices: HashMap::new(),
+            uvs: Vec::new(),
+            normals: Vec::new(),
+            face_materials: Vec::new(),
+        }
+    }
+
+    fn vertex(&mut self, corner: Corner, positions: &[Point3], tex_coords: &[(f64, f64)], normals: &[Vector3]) -> usize {
+        *self.vertices.entry(corner).or_insert_with(|| {
+            self.mesh.positions.push(positions[corner.v]);
+            self.uvs.push(corner.vt.map(|vt| tex_coords[vt]));
+            self.normals.push(corner.vn.map(|vn| normals[vn]));
+            self.mesh.positions.len() - 1
+        })
+    }
+
+    // UVs and normals are only kept if every vertex has them
+    fn finish(mut self, materials: &[Arc<Box<dyn Material>>]) -> (String, TriangleMesh) {
+        self.mesh.uvs = self.uvs.into_iter().collect();
+        self.mesh.normals = self.normals.into_iter().collect();
+        (self.name, TriangleMesh::with_materials(self.mesh, materials.to_vec(), self.face_materials))
+    }
+}
+
+// The whole file as one mesh. Also returns anything odd about the file that didn't stop it loading
+pub fn load_obj(path: &str) -> Result<(TriangleMesh, Vec<String>), String> {
+    let file = File::open(path).map_err(|err| format!("Couldn't read {}: {}", path, err))?;
+    let dir = Path::new(path).parent().unwrap_or(Path::new(""));
+    let (mut groups, warnings) = read_obj(BufReader::new(file), path, dir, false)?;
+    Ok((groups.pop().unwrap().1, warnings))
+}
+
+// A mesh for every group ("g" or "o") with faces, in the order they appear. `source` is only for messages,
+// material libraries are looked for in `dir`
+#[cfg(test)]
+pub fn read_obj_groups(reader: impl BufRead, source: &str, dir: &Path) -> Result<(Vec<(String, TriangleMesh)>, Vec<String>), String> {
+    read_obj(reader, source, dir, true)
+}
+
+fn read_obj(reader: impl BufRead, source: &str, dir: &Path, split_groups: bool) -> Result<(Vec<(String, TriangleMesh)>, Vec<String>), String> {
+    let mut warnings = Vec::new();
+    let mut positions: Vec<Point3> = Vec::new();
+    let mut tex_coords: Vec<(f64, f64)> = Vec::new();
+    let mut normals: Vec<Vector3> = Vec::new();
+
+    // Faces refer to materials by their place in this list, the first one is the default
+    let mut materials = vec![Lambertian::from_const_col(Color::from_all(0.8)).to_dyn()];
+    let mut material_names: HashMap<String, usize> = HashMap::new();
+    let mut material = 0;
+
+    let mut groups = vec![Group::new(String::from("default"))];
+
+    for (line_no, line) in reader.lines().enumerate() {
+        let line = line.map_err(|err| format!("Couldn't read {}: {}", source, err))?;
+        let location = || format!("{}:{}", source, line_no + 1);
+        let mut tokens = line.split_whitespace();
+        let Some(keyword) = tokens.next() else {continue};
+        let args: Vec<&str> = tokens.collect();
+
+        match keyword {
+            "v" => positions.push(parse_vector(&args, &location())?),
+            "vt" => {
+                let u = parse_float(args.first(), &location())?;
+                let v = if args.len() > 1 {parse_float(args.get(1), &location())?} else {0.0};
+                tex_coords.push((u, v));
+            },
+            "vn" => normals.push(parse_vector(&args, &location())?),
+            "f" => {
+                if args.len() < 3 {
+                    return Err(format!("{}: A face needs at least three vertices!", location()));
+                }
+                let group = groups.last_mut().unwrap();
+                let mut corners = Vec::with_capacity(args.len());
+                for arg in &args {
+                    let corner = parse_corner(arg, positions.len(), tex_coords.len(), normals.len(), &location())?;
+                    corners.push(group.vertex(corner, &positions, &tex_coords, &normals));
+                }
+
+                // Fan out polygons from their first vertex
+                for k in 1..(corners.len() - 1) {
+                    group.mesh.indices.push([corners[0], corners[k], corners[k + 1]]);
+                    group.face_materials.push(material);
+                }
+            },
+            "g" | "o" if split_groups => {
+                let name = if args.is_empty() {String::from("default")} else {args.join(" ")};
+                if groups.last().unwrap().mesh.indices.is_empty() {
+                    groups.pop();
+                }
+                groups.push(Group::new(name));
+            },
+            "mtllib" => {
+                for lib in &args {
+                    let path = dir.join(lib);
+                    // Faces still load without their materials, they just come out in the default one
+                    let Ok(text) = fs::read_to_string(&path) else {
+                        warnings.push(format!("{}: Couldn't read {}, using the default material", location(), path.display()));
+                        continue;
+                    };
+                    for (name, found) in read_mtl(&text, &path)? {
+                        material_names.insert(name, materials.len());
+                        materials.push(found);
+                    }
+                }
+            },
+            "usemtl" => {
+                let name = args.join(" ");
+                material = material_names.get(&name).copied().unwrap_or_else(|| {
+                    warnings.push(format!("{}: Unknown material {}, using the default", location(), name));
+                    0
+                });
+            },
+            _ => {},
+        }
+    }
+
+    if split_groups {
+        groups.retain(|group| !group.mesh.indices.is_empty());
+    }
+    Ok((groups.into_iter().map(|group| group.finish(&materials)).collect(), warnings))
+}
+
+// `path` is where `source` came from, for messages and to find textures next to it
+fn read_mtl(source: &str, path: &Path) -> Result<HashMap<String, Arc<Box<dyn Material>>>, String> {
+    let dir = path.parent().unwrap_or(Path::new(""));
+
+    let mut materials = HashMap::new();
+    let mut current: Option<(String, MtlMaterial)> = None;
+
+    for (line_no, line) in source.lines().enumerate() {
+        let location = format!("{}:{}", path.display(), line_no + 1);
+        let mut tokens = line.split_whitespace();
+        let Some(keyword) = tokens.next() else {continue};
+        let args: Vec<&str> = tokens.collect();
+
+        if keyword == "newmtl" {
+            if let Some((name, mtl)) = current.take() {
+                materials.insert(name, mtl.to_dyn());
+            }
+            current = Some((args.join(" "), MtlMaterial::new()));
+            continue;
+        }
+
+        let Some((_, mtl)) = current.as_mut() else {continue};
+        match keyword {
+            "Kd" => mtl.kd = Color::from(parse_vector(&args, &location)?),
+            "Ks" => mtl.ks = Color::from(parse_vector(&args, &location)?),
+            "Ns" => mtl.ns = parse_float(args.first(), &location)?,
+            "Ni" => mtl.ni = parse_float(args.first(), &location)?,
+            "d" => mtl.dissolve = parse_float(args.first(), &location)?,
+            "Tr" => mtl.dissolve = 1.0 - parse_float(args.first(), &location)?,
+            "illum" => mtl.illum = parse_float(args.first(), &location)? as usize,
+            // Options like -s come first, so the file name is the last argument
+            "map_Kd" => mtl.map_kd = args.last().map(|file| dir.join(file).to_string_lossy().into_owned()),
+            _ => {},
+        }
+    }
+    if let Some((name, mtl)) = current.take() {
+        materials.insert(name, mtl.to_dyn());
+    }
+    Ok(materials)
+}
+
+struct MtlMaterial {
+    kd: Color,
+    ks: Color,
+    ns: f64,
+    ni: f64,
+    dissolve: f64,
+    illum: usize,
+    map_kd: Option<String>,
+}
+
+impl MtlMaterial {
+    fn new() -> Self {
+        MtlMaterial {
+            kd: Color::from_all(0.8),
+            ks: Color::BLACK,
+            ns: 0.0,
+            ni: 1.0,
+            dissolve: 1.0,
+            illum: 2,
+            map_kd: None,
+        }
+    }
+
+    fn to_dyn(self) -> Arc<Box<dyn Material>> {
+        let max = |c: Color| c.r.max(c.g).max(c.b);
+
+        // Illumination models 4, 6, 7 and 9 are the glass ones
+        if self.dissolve < 1.0 || matches!(self.illum, 4 | 6 | 7 | 9) {
+            let index = if self.ni > 1.0 {self.ni} else {1.5};
+            return Dielectric::from(index).to_dyn();
+        }
+
+        if self.illum == 3 || (self.map_kd.is_none() && max(self.ks) > max(self.kd)) {
+            let fuzz = (2.0 / (self.ns + 2.0)).sqrt().min(1.0);
+            return Metal {albedo: self.ks, fuzz}.to_dyn();
+        }
+
+        match self.map_kd {
+            Some(file) => Lambertian::new(ImageTexture::new(file).to_box()).to_dyn(),
+            None => Lambertian::from_const_col(self.kd).to_dyn(),
+        }
+    }
+}
+
+fn parse_float(token: Option<&&str>, location: &str) -> Result<f64, String> {
+    let token = token.ok_or_else(|| format!("{}: Missing a number!", location))?;
+    token.parse().map_err(|_| format!("{}: {} isn't a number!", location, token))
+}
+
+fn parse_vector(args: &[&str], location: &str) -> Result<Vector3, String> {
+    Ok(Vector3(
+        parse_float(args.first(), location)?,
+        parse_float(args.get(1), location)?,
+        parse_float(args.get(2), location)?,
+    ))
+}
+
+// Indices start at 1, and negative ones count back from the newest element
+fn parse_index(token: &str, len: usize, location: &str) -> Result<usize, String> {
+    let index: isize = token.parse()
+        .map_err(|_| format!("{}: {} isn't an index!", location, token))?;
+    let resolved = if index < 0 {len as isize + index} else {index - 1};
+    if resolved < 0 || resolved as usize >= len {
+        return Err(format!("{}: Index {} is out of range!", location, index));
+    }
+    Ok(resolved as usize)
+}
+
+fn parse_corner(token: &str, positions: usize, tex_coords: usize, normals: usize, location: &str) -> Result<Corner, String> {
+    let mut parts = token.split('/');
+    let v = parse_index(parts.next().unwrap(), positions, location)?;
+    let vt = parts.next().filter(|part| !part.is_empty()).map(|part| parse_index(part, tex_coords, location)).transpose()?;
+    let vn = parts.next().filter(|part| !part.is_empty()).map(|part| parse_index(part, normals, location)).transpose()?;
+    Ok(Corner { v, vt, vn })
+}
@@ -0,0 +1,27 @@
+use crate::vector::Vector3;
+
+// Orthonormal basis with w along the given direction
+pub struct Onb {
+    pub u: Vector3,
+    pub v: Vector3,
+    pub w: Vector3,
+}
+
+impl Onb {
+    pub fn new(n: &Vector3) -> Self {
+        let w = n.unit();
+        let a = if w.x().abs() > 0.9 {Vector3(0.0, 1.0, 0.0)} else {Vector3(1.0, 0.0, 0.0)};
+        let v = w.cross(&a).unit();
+        let u = w.cross(&v);
+        Onb { u, v, w }
+    }
+
+    pub fn transform(&self, a: &Vector3) -> Vector3 {
+        self.u * a.x() + self.v * a.y() + self.w * a.z()
+    }
+
+    // The inverse of transform
+    pub fn local(&self, a: &Vector3) -> Vector3 {
+        Vector3(a.dot(&self.u), a.dot(&self.v), a.dot(&self.w))
+    }
+}
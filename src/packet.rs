@@ -0,0 +1,202 @@
+use std::{ops::Range, simd::Simd};
+
+use crate::{aabb::AABB, hit::{Hit, Hittable, HittableList}, interval::{Interval, SimdInterval}, ray::Ray, vector::{SimdVector3, Vector3}};
+
+pub const PACKET_SIZE: usize = 4;
+
+// Most children any PacketTree node has
+pub const MAX_CHILDREN: usize = 4;
+
+#[derive(Clone, Copy)]
+pub enum PacketNode {
+    Interior(usize),
+    // First entry and how many, for PacketTree::leaf_object
+    Leaf(usize, usize),
+}
+
+// What the packet tracer needs to walk a BVH
+pub trait PacketTree {
+    fn root(&self) -> Option<(AABB, PacketNode)>;
+
+    // Fills `children` nearest first for rays going along `dir` and returns how many it filled
+    fn children(&self, node: usize, dir: &Vector3, children: &mut [(AABB, PacketNode); MAX_CHILDREN]) -> usize;
+
+    fn leaf_object(&self, entry: usize) -> &dyn Hittable;
+
+    fn unbounded(&self) -> &HittableList;
+}
+
+// How many of a group of rays can get into a box
+enum Reach {
+    Nothing,
+    Some,
+    All,
+}
+
+// Where a group of rays start and which way they go, as ranges over the whole group
+struct PacketBounds {
+    origins: [Interval; 3],
+    inv_dirs: [Interval; 3],
+    ray_t: Interval,
+}
+
+impl PacketBounds {
+    // The slab test done in interval arithmetic, so the whole group can skip a box or go into it without
+    // testing each ray. Nothing has to be exact, since a ray left out would lose its hit. All only has to
+    // be close, since a ray let in that misses just gets tested further down
+    fn reach(&self, bbox: &AABB) -> Reach {
+        let flip = |interval: Interval| Interval {min: -interval.max, max: -interval.min};
+        let (mut earliest_near, mut latest_near) = (self.ray_t.min, self.ray_t.min);
+        let (mut earliest_far, mut latest_far) = (f64::INFINITY, self.ray_t.max);
+        let mut one_way = true;
+        for axis in 0..3 {
+            // Mirrored so every ray goes up the axis. Rays going both ways could be anywhere along it
+            let (origin, inv_dir, slab) = match self.inv_dirs[axis] {
+                inv_dir if inv_dir.min >= 0.0 => (self.origins[axis], inv_dir, bbox.axis(axis)),
+                inv_dir if inv_dir.max <= 0.0 => (flip(self.origins[axis]), flip(inv_dir), flip(bbox.axis(axis))),
+                _ => {
+                    one_way = false;
+                    continue;
+                },
+            };
+            // Smallest or largest distance times inv_dir. 0 * inf comes out NaN, which f64::max and
+            // f64::min ignore, so it bounds nothing
+            let scale = |distance: f64, smallest: bool| distance * if (distance >= 0.0) == smallest {inv_dir.min} else {inv_dir.max};
+            earliest_near = earliest_near.max(scale(slab.min - origin.max, true));
+            latest_near = latest_near.max(scale(slab.min - origin.min, false));
+            earliest_far = earliest_far.min(scale(slab.max - origin.max, true));
+            latest_far = latest_far.min(scale(slab.max - origin.min, false));
+        }
+
+        if earliest_near > latest_far {
+            Reach::Nothing
+        } else if one_way && latest_near < earliest_far {
+            Reach::All
+        } else {
+            Reach::Some
+        }
+    }
+}
+
+struct PacketTracer<'a, T: PacketTree> {
+    tree: &'a T,
+    rays: &'a [Ray],
+    ray_t: Interval,
+    hits: Vec<Option<Hit>>,
+    // Which ray is in each slot, and what the box tests need from it split by axis. Sieving moves a ray's
+    // entries together, so the four rays of a packet are always four neighbouring slots
+    ids: Vec<usize>,
+    origins: [Vec<f64>; 3],
+    inv_dirs: [Vec<f64>; 3],
+    closest: Vec<f64>,
+}
+
+// Sends every ray down the tree together. At each node the rays that miss its box are moved behind the
+// ones that hit it, and only those go on to the children, four at a time, so packets stay full however
+// far the rays spread. Where bounds on the whole group settle it, a box is skipped or gone into without
+// testing each ray
+pub fn hit_packets<T: PacketTree>(tree: &T, rays: &[Ray], ray_t: &Interval) -> Vec<Option<Hit>> {
+    let hits: Vec<Option<Hit>> = rays.iter().map(|ray| tree.unbounded().hit(ray, ray_t)).collect();
+    let closest = hits.iter().map(|hit| hit.as_ref().map_or(ray_t.max, |hit| hit.t)).collect();
+    let origins = [Vector3::x, Vector3::y, Vector3::z].map(|axis| rays.iter().map(|ray| axis(&ray.origin)).collect());
+    let inv_dirs = [Vector3::x, Vector3::y, Vector3::z].map(|axis| rays.iter().map(|ray| 1.0 / axis(&ray.dir)).collect());
+    let mut tracer = PacketTracer {tree, rays, ray_t: *ray_t, hits, ids: (0..rays.len()).collect(), origins, inv_dirs, closest};
+
+    if let Some((bbox, root)) = tree.root() {
+        let kept = tracer.sieve(&bbox, 0..rays.len());
+        if kept > 0 {
+            tracer.visit(root, 0..kept, None);
+        }
+    }
+    tracer.hits
+}
+
+impl<T: PacketTree> PacketTracer<'_, T> {
+    // Every ray in `slots` may reach `node`. Children only shuffle the rays within `slots`, so the caller's
+    // range still holds the same ones afterwards. `bounds` are for these rays, if the caller already has them
+    fn visit(&mut self, node: PacketNode, slots: Range<usize>, bounds: Option<&PacketBounds>) {
+        match node {
+            PacketNode::Leaf(start, count) => {
+                for slot in slots {
+                    let id = self.ids[slot];
+                    for entry in start..(start + count) {
+                        let range = Interval {min: self.ray_t.min, max: self.closest[slot]};
+                        if let Some(hit) = self.tree.leaf_object(entry).hit(&self.rays[id], &range) {
+                            self.closest[slot] = hit.t;
+                            self.hits[id] = Some(hit);
+                        }
+                    }
+                }
+            },
+            PacketNode::Interior(index) => {
+                // Rays in a packet go roughly the same way, so the first one picks the order for all of them
+                let dir = self.rays[self.ids[slots.start]].dir;
+                let mut children = [(AABB::EMPTY, PacketNode::Leaf(0, 0)); MAX_CHILDREN];
+                let count = self.tree.children(index, &dir, &mut children);
+
+                let own_bounds;
+                let bounds = match bounds {
+                    Some(bounds) => bounds,
+                    None => {
+                        own_bounds = self.bounds(slots.clone());
+                        &own_bounds
+                    },
+                };
+                for (child_bbox, child) in &children[..count] {
+                    match bounds.reach(child_bbox) {
+                        Reach::Nothing => {},
+                        Reach::All => self.visit(*child, slots.clone(), Some(bounds)),
+                        Reach::Some => {
+                            let kept = self.sieve(child_bbox, slots.clone());
+                            if kept > 0 {
+                                self.visit(*child, slots.start..(slots.start + kept), None);
+                            }
+                        },
+                    }
+                }
+            },
+        }
+    }
+
+    // Moves the rays in `slots` that reach `bbox` before anything they've already hit to the front
+    // and returns how many there are
+    fn sieve(&mut self, bbox: &AABB, slots: Range<usize>) -> usize {
+        let mut kept = slots.start;
+        for start in slots.clone().step_by(PACKET_SIZE) {
+            // The last packet may be short. Its spare lanes are zeros and get masked off below
+            let load = |values: &[f64]| Simd::<f64, PACKET_SIZE>::load_or_default(&values[start..slots.end]);
+            let origins = SimdVector3(load(&self.origins[0]), load(&self.origins[1]), load(&self.origins[2]));
+            let inv_dirs = SimdVector3(load(&self.inv_dirs[0]), load(&self.inv_dirs[1]), load(&self.inv_dirs[2]));
+            let ray_t = SimdInterval {mins: Simd::splat(self.ray_t.min), maxes: load(&self.closest)};
+
+            let live = (slots.end - start).min(PACKET_SIZE);
+            let mut hits = bbox.simd_test_inv(&origins, &inv_dirs, &ray_t).to_bitmask() & ((1 << live) - 1);
+            // Lanes come out in order, and every slot before this lane is either kept or one already looked at
+            while hits != 0 {
+                self.swap(kept, start + hits.trailing_zeros() as usize);
+                kept += 1;
+                hits &= hits - 1;
+            }
+        }
+        kept - slots.start
+    }
+
+    fn bounds(&self, slots: Range<usize>) -> PacketBounds {
+        let enclose = |values: &[f64]| values.iter().fold(Interval::EMPTY, |bounds, &x| Interval {min: bounds.min.min(x), max: bounds.max.max(x)});
+        PacketBounds {
+            origins: [0, 1, 2].map(|axis| enclose(&self.origins[axis][slots.clone()])),
+            inv_dirs: [0, 1, 2].map(|axis| enclose(&self.inv_dirs[axis][slots.clone()])),
+            ray_t: Interval {min: self.ray_t.min, max: enclose(&self.closest[slots]).max},
+        }
+    }
+
+    fn swap(&mut self, a: usize, b: usize) {
+        if a == b {return;}
+        self.ids.swap(a, b);
+        for axis in 0..3 {
+            self.origins[axis].swap(a, b);
+            self.inv_dirs[axis].swap(a, b);
+        }
+        self.closest.swap(a, b);
+    }
+}
@@ -0,0 +1,30 @@
+use std::io::Write;
+
+use crate::{encoder::ImageEncoder, framebuffer::{FrameBuffer, Layer}};
+
+pub fn write_pfm<W: Write>(writer: &mut W, frame: &FrameBuffer) {
+    // A negative scale marks the data as little-endian
+    writer.write_all(
+        format!("PF\n{} {}\n-1.0\n", frame.width(), frame.height()).as_bytes()
+    ).unwrap();
+
+    // Scanlines are stored bottom to top
+    let mut data = Vec::with_capacity(frame.width() * frame.height() * 12);
+    for j in (0..frame.height()).rev() {
+        for i in 0..frame.width() {
+            let color = frame.get(i, j);
+            for component in [color.r, color.g, color.b] {
+                data.extend_from_slice(&(component as f32).to_le_bytes());
+            }
+        }
+    }
+    writer.write_all(&data).unwrap();
+}
+
+pub struct PfmEncoder;
+
+impl ImageEncoder for PfmEncoder {
+    fn encode(&self, mut writer: &mut dyn Write, layers: &[Layer]) {
+        write_pfm(&mut writer, &layers[0].buffer);
+    }
+}
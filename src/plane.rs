@@ -0,0 +1,53 @@
+use std::sync::Arc;
+
+use crate::{aabb::AABB, hit::{Hit, Hittable}, interval::Interval, material::Material, onb::Onb, ray::Ray, vector::{Point3, Vector3}};
+
+// An infinite plane through point. Its bounds are infinite too, so BVHNode keeps it out of the tree
+pub struct Plane {
+    point: Point3,
+    frame: Onb,
+    d: f64,
+    material: Arc<Box<dyn Material>>,
+}
+
+impl Plane {
+    pub fn new(point: Point3, normal: Vector3, material: Arc<Box<dyn Material>>) -> Self {
+        let frame = Onb::new(&normal);
+        Plane { point, d: frame.w.dot(&point), frame, material }
+    }
+
+    pub fn as_box(self) -> Box<dyn Hittable> {
+        Box::new(self)
+    }
+}
+
+impl Hittable for Plane {
+    fn hit(&self, ray: &Ray, ray_t: &Interval) -> Option<Hit> {
+        let normal = self.frame.w;
+        let denom = normal.dot(&ray.dir);
+        if denom.abs() < 1e-8 {return None;}
+
+        let t = (self.d - normal.dot(&ray.origin)) / denom;
+        if !ray_t.surrounds(t) {return None;}
+
+        // Textures repeat once per unit along the plane
+        let p = ray.at(t);
+        let offset = p - self.point;
+
+        let front_face = denom < 0.0;
+        Some(Hit {
+            p,
+            normal: if front_face {normal} else {-normal},
+            material: self.material.clone(),
+            t,
+            u: offset.dot(&self.frame.u).rem_euclid(1.0),
+            v: offset.dot(&self.frame.v).rem_euclid(1.0),
+            front_face,
+            color: None,
+        })
+    }
+
+    fn bounding(&self) -> &AABB {
+        &AABB::UNIVERSE
+    }
+}
@@ -0,0 +1,214 @@
+use std::{fs, str::SplitAsciiWhitespace, sync::Arc};
+
+use crate::{color::Color, material::Material, mesh::Mesh, triangle_mesh::TriangleMesh, vector::Vector3};
+
+#[derive(Clone, Copy, PartialEq)]
+enum Scalar {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+    F64,
+}
+
+impl Scalar {
+    fn parse(name: &str) -> Result<Self, String> {
+        match name {
+            "char" | "int8" => Ok(Scalar::I8),
+            "uchar" | "uint8" => Ok(Scalar::U8),
+            "short" | "int16" => Ok(Scalar::I16),
+            "ushort" | "uint16" => Ok(Scalar::U16),
+            "int" | "int32" => Ok(Scalar::I32),
+            "uint" | "uint32" => Ok(Scalar::U32),
+            "float" | "float32" => Ok(Scalar::F32),
+            "double" | "float64" => Ok(Scalar::F64),
+            _ => Err(format!("Unknown PLY property type {}!", name)),
+        }
+    }
+
+    fn size(&self) -> usize {
+        match self {
+            Scalar::I8 | Scalar::U8 => 1,
+            Scalar::I16 | Scalar::U16 => 2,
+            Scalar::I32 | Scalar::U32 | Scalar::F32 => 4,
+            Scalar::F64 => 8,
+        }
+    }
+
+    // Integer colors are stored as fractions of the type's maximum
+    fn color_scale(&self) -> f64 {
+        match self {
+            Scalar::U8 => 255.0,
+            Scalar::U16 => 65535.0,
+            _ => 1.0,
+        }
+    }
+}
+
+enum Property {
+    Scalar(String, Scalar),
+    List(String, Scalar, Scalar),
+}
+
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<Property>,
+}
+
+enum Body<'a> {
+    Ascii(SplitAsciiWhitespace<'a>),
+    Binary(&'a [u8]),
+}
+
+impl Body<'_> {
+    fn read(&mut self, kind: Scalar) -> Result<f64, String> {
+        match self {
+            Body::Ascii(tokens) => {
+                let token = tokens.next().ok_or("The PLY file ended early!")?;
+                token.parse().map_err(|_| format!("{} isn't a number!", token))
+            },
+            Body::Binary(data) => {
+                if data.len() < kind.size() {
+                    return Err(String::from("The PLY file ended early!"));
+                }
+                let (bytes, rest) = data.split_at(kind.size());
+                *data = rest;
+                Ok(match kind {
+                    Scalar::I8 => bytes[0] as i8 as f64,
+                    Scalar::U8 => bytes[0] as f64,
+                    Scalar::I16 => i16::from_le_bytes(bytes.try_into().unwrap()) as f64,
+                    Scalar::U16 => u16::from_le_bytes(bytes.try_into().unwrap()) as f64,
+                    Scalar::I32 => i32::from_le_bytes(bytes.try_into().unwrap()) as f64,
+                    Scalar::U32 => u32::from_le_bytes(bytes.try_into().unwrap()) as f64,
+                    Scalar::F32 => f32::from_le_bytes(bytes.try_into().unwrap()) as f64,
+                    Scalar::F64 => f64::from_le_bytes(bytes.try_into().unwrap()),
+                })
+            },
+        }
+    }
+}
+
+// Vertex colors win over `material` when the file has them
+pub fn load_ply(path: &str, material: Arc<Box<dyn Material>>) -> Result<TriangleMesh, String> {
+    let data = fs::read(path).map_err(|err| format!("Couldn't read {}: {}", path, err))?;
+    let mesh = read_ply(&data)?;
+    Ok(if mesh.colors.is_some() {TriangleMesh::from_vertex_colors(mesh)} else {TriangleMesh::new(mesh, material)})
+}
+
+pub fn read_ply(data: &[u8]) -> Result<Mesh, String> {
+    let end = b"end_header";
+    let header_end = data.windows(end.len()).position(|window| window == end)
+        .ok_or("This isn't a PLY file, it has no end_header!")?;
+    let body_start = header_end + data[header_end..].iter().position(|&b| b == b'\n')
+        .ok_or("The PLY header never ends!")? + 1;
+    let header = std::str::from_utf8(&data[..header_end]).map_err(|_| "The PLY header isn't text!")?;
+
+    let mut lines = header.lines();
+    if lines.next().map(str::trim) != Some("ply") {
+        return Err(String::from("This isn't a PLY file!"));
+    }
+
+    let mut binary = false;
+    let mut elements: Vec<Element> = Vec::new();
+    for line in lines {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.as_slice() {
+            ["format", "ascii", _] => binary = false,
+            ["format", "binary_little_endian", _] => binary = true,
+            ["format", format, _] => return Err(format!("PLY format {} isn't supported!", format)),
+            ["element", name, count] => elements.push(Element {
+                name: name.to_string(),
+                count: count.parse().map_err(|_| format!("{} isn't an element count!", count))?,
+                properties: Vec::new(),
+            }),
+            ["property", "list", count, item, name] => elements.last_mut()
+                .ok_or("A PLY property came before any element!")?
+                .properties.push(Property::List(name.to_string(), Scalar::parse(count)?, Scalar::parse(item)?)),
+            ["property", kind, name] => elements.last_mut()
+                .ok_or("A PLY property came before any element!")?
+                .properties.push(Property::Scalar(name.to_string(), Scalar::parse(kind)?)),
+            _ => {},
+        }
+    }
+
+    let mut body = if binary {
+        Body::Binary(&data[body_start..])
+    } else {
+        Body::Ascii(std::str::from_utf8(&data[body_start..]).map_err(|_| "The ASCII PLY body isn't text!")?.split_ascii_whitespace())
+    };
+
+    // Faces can only point at vertices the header promised
+    let vertices = elements.iter().find(|element| element.name == "vertex").map_or(0, |element| element.count);
+
+    let mut mesh = Mesh::new();
+    for element in &elements {
+        let has = |names: &[&str]| element.properties.iter()
+            .any(|property| matches!(property, Property::Scalar(name, _) if names.contains(&name.as_str())));
+        if element.name == "vertex" {
+            if has(&["nx"]) {mesh.normals = Some(Vec::with_capacity(element.count));}
+            if has(&["u", "s", "texture_u"]) {mesh.uvs = Some(Vec::with_capacity(element.count));}
+            if has(&["red", "r", "diffuse_red"]) {mesh.colors = Some(Vec::with_capacity(element.count));}
+        }
+
+        for _ in 0..element.count {
+            let mut position = Vector3(0.0, 0.0, 0.0);
+            let mut normal = Vector3(0.0, 0.0, 0.0);
+            let mut uv = (0.0, 0.0);
+            let mut color = Color::BLACK;
+
+            for property in &element.properties {
+                match property {
+                    Property::Scalar(name, kind) => {
+                        let value = body.read(*kind)?;
+                        if element.name != "vertex" {continue;}
+                        match name.as_str() {
+                            "x" => position.0 = value,
+                            "y" => position.1 = value,
+                            "z" => position.2 = value,
+                            "nx" => normal.0 = value,
+                            "ny" => normal.1 = value,
+                            "nz" => normal.2 = value,
+                            "u" | "s" | "texture_u" => uv.0 = value,
+                            "v" | "t" | "texture_v" => uv.1 = value,
+                            "red" | "r" | "diffuse_red" => color.r = value / kind.color_scale(),
+                            "green" | "g" | "diffuse_green" => color.g = value / kind.color_scale(),
+                            "blue" | "b" | "diffuse_blue" => color.b = value / kind.color_scale(),
+                            _ => {},
+                        }
+                    },
+                    Property::List(name, count, item) => {
+                        let count = body.read(*count)?;
+                        if count < 0.0 || count.fract() != 0.0 {
+                            return Err(format!("{} isn't a PLY list length!", count));
+                        }
+                        let items = (0..count as usize).map(|_| body.read(*item)).collect::<Result<Vec<f64>, String>>()?;
+                        if element.name != "face" || !(name == "vertex_indices" || name == "vertex_index") {
+                            continue;
+                        }
+                        if let Some(index) = items.iter().find(|&&index| index < 0.0 || index >= vertices as f64 || index.fract() != 0.0) {
+                            return Err(format!("A PLY face points at vertex {}, but there are only {} vertices!", index, vertices));
+                        }
+                        let items: Vec<usize> = items.into_iter().map(|index| index as usize).collect();
+                        // Fan out polygons from their first vertex
+                        for k in 1..items.len().saturating_sub(1) {
+                            mesh.indices.push([items[0], items[k], items[k + 1]]);
+                        }
+                    },
+                }
+            }
+
+            if element.name == "vertex" {
+                mesh.positions.push(position);
+                if let Some(normals) = &mut mesh.normals {normals.push(normal);}
+                if let Some(uvs) = &mut mesh.uvs {uvs.push(uv);}
+                if let Some(colors) = &mut mesh.colors {colors.push(color);}
+            }
+        }
+    }
+
+    Ok(mesh)
+}
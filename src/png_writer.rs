@@ -0,0 +1,44 @@
+use std::io::Write;
+
+use png::{BitDepth, ColorType, Encoder, ScaledFloat, SourceChromaticities, SrgbRenderingIntent};
+
+use crate::{color::linear_to_srgb, encoder::ImageEncoder, framebuffer::{FrameBuffer, Layer}, interval::Interval};
+
+pub fn write_png<W: Write>(writer: W, frame: &FrameBuffer, depth: BitDepth) {
+    let mut encoder = Encoder::new(writer, frame.width() as u32, frame.height() as u32);
+    encoder.set_color(ColorType::Rgb);
+    encoder.set_depth(depth);
+    // The sRGB chunk, plus the gAMA and cHRM values the spec gives as its fallback for older readers
+    encoder.set_source_srgb(SrgbRenderingIntent::Perceptual);
+    encoder.set_source_gamma(ScaledFloat::from_scaled(45455));
+    encoder.set_source_chromaticities(SourceChromaticities::new((0.3127, 0.3290), (0.64, 0.33), (0.30, 0.60), (0.15, 0.06)));
+
+    let intensity = Interval {min: 0.0, max: 1.0};
+    let mut data = Vec::new();
+    for color in frame.snapshot() {
+        for component in [color.r, color.g, color.b] {
+            let value = intensity.clamp(linear_to_srgb(component));
+            match depth {
+                BitDepth::Eight => data.push((value * 255.0).round() as u8),
+                BitDepth::Sixteen => data.extend_from_slice(
+                    &((value * 65535.0).round() as u16).to_be_bytes()
+                ),
+                _ => panic!("Only 8 and 16 bit PNG output is supported!"),
+            }
+        }
+    }
+
+    let mut writer = encoder.write_header().unwrap();
+    writer.write_image_data(&data).unwrap();
+    writer.finish().unwrap();
+}
+
+pub struct PngEncoder {
+    pub depth: BitDepth,
+}
+
+impl ImageEncoder for PngEncoder {
+    fn encode(&self, writer: &mut dyn Write, layers: &[Layer]) {
+        write_png(writer, &layers[0].buffer, self.depth);
+    }
+}
@@ -0,0 +1,108 @@
+use std::sync::Arc;
+
+use rand::random;
+
+use crate::{aabb::AABB, hit::{Hit, HittableList, Hittable}, interval::Interval, material::Material, ray::Ray, vector::{Point3, Vector3}};
+
+// A parallelogram with one corner at q and edges u and v
+pub struct Quad {
+    q: Point3,
+    u: Vector3,
+    v: Vector3,
+    // n / (n . n), so a point's planar coordinates are cheap to find
+    w: Vector3,
+    normal: Vector3,
+    d: f64,
+    area: f64,
+    material: Arc<Box<dyn Material>>,
+    bbox: AABB,
+}
+
+impl Quad {
+    pub fn new(q: Point3, u: Vector3, v: Vector3, material: Arc<Box<dyn Material>>) -> Self {
+        let n = u.cross(&v);
+        let normal = n.unit();
+        let bbox = AABB::enclose(&AABB::from(q, q + u + v), &AABB::from(q + u, q + v)).pad(1e-4);
+        Quad {
+            q,
+            u,
+            v,
+            w: n / n.dot(&n),
+            normal,
+            d: normal.dot(&q),
+            area: n.length(),
+            material,
+            bbox,
+        }
+    }
+
+    pub fn as_box(self) -> Box<dyn Hittable> {
+        Box::new(self)
+    }
+}
+
+impl Hittable for Quad {
+    fn hit(&self, ray: &Ray, ray_t: &Interval) -> Option<Hit> {
+        let denom = self.normal.dot(&ray.dir);
+        if denom.abs() < 1e-8 {return None;}
+
+        let t = (self.d - self.normal.dot(&ray.origin)) / denom;
+        if !ray_t.surrounds(t) {return None;}
+
+        let p = ray.at(t);
+        let planar = p - self.q;
+        let alpha = self.w.dot(&planar.cross(&self.v));
+        let beta = self.w.dot(&self.u.cross(&planar));
+        if !(0.0..=1.0).contains(&alpha) || !(0.0..=1.0).contains(&beta) {return None;}
+
+        let front_face = denom < 0.0;
+        Some(Hit {
+            p,
+            normal: if front_face {self.normal} else {-self.normal},
+            material: self.material.clone(),
+            t,
+            u: alpha,
+            v: beta,
+            front_face,
+            color: None,
+        })
+    }
+
+    fn bounding(&self) -> &AABB {
+        &self.bbox
+    }
+
+    fn pdf_value(&self, origin: &Point3, dir: &Vector3, time: f64) -> f64 {
+        let ray = Ray {origin: *origin, dir: *dir, time};
+        let Some(hit) = self.hit(&ray, &Interval {min: 0.001, max: f64::INFINITY}) else {
+            return 0.0;
+        };
+
+        let dist_squared = hit.t * hit.t * dir.length_squared();
+        let cosine = (dir.dot(&self.normal) / dir.length()).abs();
+        dist_squared / (cosine * self.area)
+    }
+
+    fn random(&self, origin: &Point3, _time: f64) -> Vector3 {
+        self.q + self.u * random::<f64>() + self.v * random::<f64>() - *origin
+    }
+}
+
+// The six faces of the box with opposite corners a and b, all facing outwards
+pub fn make_box(a: Point3, b: Point3, material: Arc<Box<dyn Material>>) -> HittableList {
+    let min = Vector3(a.x().min(b.x()), a.y().min(b.y()), a.z().min(b.z()));
+    let max = Vector3(a.x().max(b.x()), a.y().max(b.y()), a.z().max(b.z()));
+
+    let dx = Vector3(max.x() - min.x(), 0.0, 0.0);
+    let dy = Vector3(0.0, max.y() - min.y(), 0.0);
+    let dz = Vector3(0.0, 0.0, max.z() - min.z());
+
+    let mut sides = HittableList::new();
+    sides.add(Quad::new(Vector3(min.x(), min.y(), max.z()), dx, dy, material.clone()).as_box()); // front
+    sides.add(Quad::new(Vector3(max.x(), min.y(), max.z()), -dz, dy, material.clone()).as_box()); // right
+    sides.add(Quad::new(Vector3(max.x(), min.y(), min.z()), -dx, dy, material.clone()).as_box()); // back
+    sides.add(Quad::new(Vector3(min.x(), min.y(), min.z()), dz, dy, material.clone()).as_box()); // left
+    sides.add(Quad::new(Vector3(min.x(), max.y(), max.z()), dx, -dz, material.clone()).as_box()); // top
+    sides.add(Quad::new(Vector3(min.x(), min.y(), min.z()), dx, dz, material).as_box()); // bottom
+    sides
+}
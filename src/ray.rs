@@ -1,6 +1,6 @@
-use std::{ops::Div, simd::{LaneCount, Mask, Simd, SupportedLaneCount}, sync::{Arc, Mutex}};
+use std::{simd::{LaneCount, Mask, Simd, SupportedLaneCount}, sync::{Arc, Mutex}};
 
-use crate::{color::Color, hit::Hittable, interval::Interval, math::lerp, simd::{Maskish, Simdish}, vector::{Point3, SimdPoint3, SimdVector3, SimdVector3Mask, Vector3}, writer::Debugger};
+use crate::{background::Background, color::Color, hit::{Hit, Hittable, HittableList}, interval::Interval, math::power_heuristic, simd::{Maskish, Simdish}, vector::{Point3, SimdPoint3, SimdVector3, SimdVector3Mask, Vector3}, writer::Debugger};
 
 #[derive(Clone, Copy)]
 pub struct Ray {
@@ -9,6 +9,7 @@ pub struct Ray {
     pub time: f64,
 }
 
+#[derive(Clone, Copy)]
 pub struct SimdRay<const N: usize>
     where LaneCount<N>: SupportedLaneCount {
     pub origins: SimdPoint3<N>,
@@ -16,50 +17,87 @@ pub struct SimdRay<const N: usize>
     pub times: Simd<f64, N>
 }
 
+// What the first surface a camera ray sees looks like, for the AOV layers
+pub struct Features {
+    pub albedo: Color,
+    pub normal: Vector3,
+    pub depth: f64,
+}
+
 impl Ray {
     pub fn at(&self, t: f64) -> Point3 {
         self.origin + self.dir * t
     }
 
-    pub fn color(&self, max_depth: usize, world: &dyn Hittable, debugger: Arc<Mutex<Debugger>>) -> Color {
+    pub fn color(&self, max_depth: usize, world: &dyn Hittable, lights: &HittableList, background: &Background, debugger: Arc<Mutex<Debugger>>) -> Color {
+        self.trace(max_depth, world, lights, background, None, debugger)
+    }
+
+    // Same as color, along with what the first surface the ray hits looks like. `hit` is that first hit,
+    // found by the caller so a pixel's rays can be traced together with Hittable::hit_all
+    pub fn color_and_features(&self, hit: Option<Hit>, max_depth: usize, world: &dyn Hittable, lights: &HittableList, background: &Background, debugger: Arc<Mutex<Debugger>>) -> (Color, Option<Features>) {
+        if max_depth <= 0 {return (Color::BLACK, None);}
+
+        match hit {
+            Some(hit) => (self.shade(&hit, max_depth, world, lights, background, None, debugger.clone()), Some(self.features(&hit, debugger))),
+            None => (background.value(&self.dir, debugger), None),
+        }
+    }
+
+    fn features(&self, hit: &Hit, debugger: Arc<Mutex<Debugger>>) -> Features {
+        Features {
+            albedo: hit.material.albedo(hit, debugger),
+            normal: hit.normal,
+            depth: hit.t * self.dir.length(),
+        }
+    }
+
+    // `bsdf_pdf` is the density this ray was sampled with, if light sampling could also have found it
+    fn trace(&self, max_depth: usize, world: &dyn Hittable, lights: &HittableList, background: &Background, bsdf_pdf: Option<f64>, debugger: Arc<Mutex<Debugger>>) -> Color {
         if max_depth <= 0 {return Color::BLACK;}
         
         match world.hit(self, &Interval {min: 0.001, max: f64::INFINITY}) {
-            Some(hit) => {
-                if let Some((attenuation, new_dir)) =
-                    hit.material.scatter(self, &hit, debugger.clone())
-                {
-                    attenuation * new_dir.color(max_depth - 1, world, debugger.clone())
-                } else {
-                    Color::BLACK
-                }
-
-            }
-            None => {
-                let unit_dir = self.dir.unit();
-                let a = 0.5 * (unit_dir.y() + 1.0);
-                let white = Color { r: 1.0, g: 1.0, b: 1.0 };
-                let blue = Color { r: 0.5, g: 0.7, b: 1.0 };
-                lerp(&white, &blue, a)
-            },
+            Some(hit) => self.shade(&hit, max_depth, world, lights, background, bsdf_pdf, debugger),
+            None => background.value(&self.dir, debugger),
         }
     }
-}
 
-impl<const N: usize> SimdRay<N>
-    where LaneCount<N>: SupportedLaneCount {
-    pub fn from_array(array: [Ray; N]) -> Self {
-        let mut origins = [Vector3::new(); N];
-        let mut dirs = [Vector3::new(); N];
-        let mut times = [0.0; N];
-
-        for index in 0..N {
-            origins[index] = array[index].origin;
-            dirs[index] = array[index].dir;
-            times[index] = array[index].time;
+    // Light leaving `hit` back along this ray
+    pub fn shade(&self, hit: &Hit, max_depth: usize, world: &dyn Hittable, lights: &HittableList, background: &Background, bsdf_pdf: Option<f64>, debugger: Arc<Mutex<Debugger>>) -> Color {
+        let mut emitted = hit.material.emitted(hit.u, hit.v, &hit.p, debugger.clone());
+        if let Some(bsdf_pdf) = bsdf_pdf {
+            let light_pdf = lights.pdf_value(&self.origin, &self.dir, self.time);
+            emitted = emitted * power_heuristic(bsdf_pdf, light_pdf);
+        }
+
+        let Some(rec) = hit.material.scatter(self, hit, debugger.clone()) else {
+            return emitted;
+        };
+
+        if rec.specular || lights.num_objects() == 0 {
+            // Specular bounces can only find lights by following the scattered ray
+            return emitted + rec.attenuation * rec.scattered.trace(max_depth - 1, world, lights, background, None, debugger.clone());
         }
 
-        SimdRay { origins: SimdVector3::from_array(origins), dirs: SimdVector3::from_array(dirs), times: Simd::from_array(times) }
+        let direct = self.sample_light(hit, world, lights, debugger.clone());
+        let indirect = rec.attenuation * rec.scattered.trace(max_depth - 1, world, lights, background, Some(rec.pdf), debugger.clone());
+        emitted + direct + indirect
+    }
+
+    // Casts a shadow ray towards a light and weighs what it finds against BSDF sampling
+    fn sample_light(&self, hit: &Hit, world: &dyn Hittable, lights: &HittableList, debugger: Arc<Mutex<Debugger>>) -> Color {
+        let shadow = Ray {origin: hit.p, dir: lights.random(&hit.p, self.time), time: self.time};
+        let light_pdf = lights.pdf_value(&shadow.origin, &shadow.dir, self.time);
+        let bsdf_pdf = hit.material.pdf(self, hit, &shadow);
+        if light_pdf <= 0.0 || bsdf_pdf <= 0.0 {return Color::BLACK;}
+
+        let Some(light_hit) = world.hit(&shadow, &Interval {min: 0.001, max: f64::INFINITY}) else {
+            return Color::BLACK;
+        };
+        let emitted = light_hit.material.emitted(light_hit.u, light_hit.v, &light_hit.p, debugger.clone());
+        let bsdf = hit.material.eval(self, hit, &shadow, debugger);
+
+        bsdf * emitted * (power_heuristic(light_pdf, bsdf_pdf) / light_pdf)
     }
 }
 
@@ -75,9 +113,26 @@ impl<const N: usize> Simdish for SimdRay<N>
     where LaneCount<N>: SupportedLaneCount {
     type Unpacked = Ray;
     type Mask = SimdRayMask<N>;
+    const LANES: usize = N;
 
     fn replace(self, replace: Self, mask: <Self as Simdish>::Mask) -> Self {
-        todo!()
+        SimdRay {
+            origins: self.origins.replace(replace.origins, mask.origins),
+            dirs: self.dirs.replace(replace.dirs, mask.dirs),
+            times: mask.times.select(replace.times, self.times),
+        }
+    }
+
+    fn lane(&self, index: usize) -> Self::Unpacked {
+        Ray {origin: self.origins.lane(index), dir: self.dirs.lane(index), time: self.times[index]}
+    }
+
+    fn from_lanes(lanes: &[Self::Unpacked]) -> Self {
+        SimdRay {
+            origins: SimdVector3::from_lanes(&lanes.iter().map(|ray| ray.origin).collect::<Vec<_>>()),
+            dirs: SimdVector3::from_lanes(&lanes.iter().map(|ray| ray.dir).collect::<Vec<_>>()),
+            times: Simd::from_array(std::array::from_fn(|index| lanes[index].time)),
+        }
     }
 }
 
@@ -85,6 +140,20 @@ impl<const N: usize> Maskish for SimdRayMask<N>
     where LaneCount<N>: SupportedLaneCount {
     fn replace(self, replace: Self, mask: Self) -> Self {
         let origins = self.origins.replace(replace.origins, mask.origins);
-        todo!()
+        let dirs = self.dirs.replace(replace.dirs, mask.dirs);
+        let times = self.times.replace(replace.times, mask.times);
+        SimdRayMask {origins, dirs, times}
+    }
+
+    fn lane(&self, index: usize) -> bool {
+        self.origins.lane(index) && self.dirs.lane(index) && self.times.test(index)
     }
-}
\ No newline at end of file
+
+    fn from_lanes(lanes: &[bool]) -> Self {
+        SimdRayMask {
+            origins: SimdVector3Mask::from_lanes(lanes),
+            dirs: SimdVector3Mask::from_lanes(lanes),
+            times: Mask::from_lanes(lanes),
+        }
+    }
+}
@@ -1,110 +1,143 @@
-use std::{collections::VecDeque, fs::File, io::BufWriter, sync::{Arc, Mutex}};
+use std::{fs::File, io::BufWriter, sync::{Arc, Mutex}};
 
 use indicatif::{ProgressBar, ProgressStyle};
 
-use crate::{bvh::BVHNode, color::{Color, Pixel}, hit::Hittable, interval::Interval, ray::{Ray, SimdRay}, scene::Scene, simd::SimdVec, vector::Vector3, writer::{Debugger, ImgWriter}, FILE_OUT};
+use crate::{camera::Camera, color::{Color, Pixel}, framebuffer::Layer, hit::Hit, interval::Interval, ray::Ray, scene::Scene, tile::{TileOptions, TileScheduler}, vector::Vector3, writer::{Debugger, ImgWriter, OutputFormat}, FILE_OUT, OUTPUT_FORMAT};
 
 pub trait Renderer {
     fn render(&self, scene: Scene);
 }
 
-pub struct DefaultRenderer;
+// Shares the tile scheduler, output and progress reporting between renderers.
+// Any AOV layers are filled from the values `shade` leaves in its slice, in layer order
+fn render_tiles<F>(scene: &Scene, tiles: TileOptions, aovs: Vec<Layer>, shade: F)
+    where F: Fn(&Camera, usize, usize, &mut [f64]) -> Color + Send + Sync + 'static {
+    let mut layers = vec![Layer::beauty(scene.camera.img_width, scene.camera.img_height)];
+    layers.extend(aovs);
+    let layers = Arc::new(layers);
+    let aov_channels: usize = layers[1..].iter().map(|layer| layer.buffer.channels()).sum();
+
+    let pixel_progress = Arc::new(
+        ProgressBar::new((scene.camera.img_height*scene.camera.img_width) as u64)
+            .with_style(ProgressStyle::with_template("{human_pos}/{len} {wide_bar:.green} {elapsed}").unwrap())
+    );
+
+    let scheduler = TileScheduler::new(
+        scene.camera.img_width,
+        scene.camera.img_height,
+        tiles,
+        scene.camera.threadpool.max_count(),
+    );
+
+    let camera = scene.camera.clone();
+    let progress = pixel_progress.clone();
+    let tile_layers = layers.clone();
+    scheduler.run(&scene.camera.threadpool, move |tile| {
+        let mut values = vec![0.0; aov_channels];
+        for (i, j) in tile.pixels() {
+            let color = shade(&camera, i, j, &mut values);
+            tile_layers[0].buffer.write(Pixel {color, i, j});
+
+            let mut start = 0;
+            for layer in &tile_layers[1..] {
+                let end = start + layer.buffer.channels();
+                layer.buffer.write_values(i, j, &values[start..end]);
+                start = end;
+            }
+        }
+        progress.inc(tile.area() as u64);
+    });
+
+    pixel_progress.finish();
+    let file = File::create(FILE_OUT).unwrap();
+    let format = OUTPUT_FORMAT.unwrap_or_else(|| OutputFormat::from_path(FILE_OUT));
+    ImgWriter::new(BufWriter::new(file), format.encoder()).write(&layers);
+}
+
+pub struct DefaultRenderer {
+    tiles: TileOptions,
+}
 
 impl DefaultRenderer {
-    pub fn new() -> Box<dyn Renderer> {
-        Box::new(Self{})
+    pub fn new(tiles: TileOptions) -> Box<dyn Renderer> {
+        Box::new(Self{tiles})
     }
 }
 
 impl Renderer for DefaultRenderer {
     fn render(&self, scene: Scene) {
         let debugger = Arc::new(Mutex::new(Debugger::new("debug.txt")));
-
-        let progress = ProgressBar::new_spinner().with_message("Generating Rays...");
-        let mut rays = VecDeque::new();
-
-        // Generate rays to evaluate
-        for j in 0..(scene.camera.img_height) {
-            for i in 0..(scene.camera.img_width) {
-                for _ in 0..(scene.camera.samples_per_pixel) {
-                    rays.push_back(scene.camera.get_ray(i, j));
+        let root = scene.root.clone();
+        let background = scene.background.clone();
+        let lights = scene.lights.clone();
+        let (width, height) = (scene.camera.img_width, scene.camera.img_height);
+        let aovs = vec![
+            Layer::new("albedo", &["R", "G", "B"], width, height),
+            Layer::new("normal", &["X", "Y", "Z"], width, height),
+            Layer::new("depth", &["Z"], width, height),
+        ];
+
+        render_tiles(&scene, self.tiles, aovs, move |camera, i, j, aov| {
+            let mut color = Color::BLACK;
+            let mut albedo = Color::BLACK;
+            let mut normal = Vector3::new();
+            // Depth is the nearest first hit of any sample, so edges keep the front surface rather than
+            // blending with what's behind. Infinite where nothing was hit
+            let mut depth = f64::INFINITY;
+            let mut hit_samples = 0;
+            // Only the first hits go as packets. Bounces scatter too far apart to gain from it
+            let rays: Vec<Ray> = camera.pixel_rays(i, j).collect();
+            let hits = root.hit_all(&rays, &Interval {min: 0.001, max: f64::INFINITY});
+            for (ray, hit) in rays.iter().zip(hits) {
+                let (sample, features) = ray.color_and_features(hit, camera.max_depth, &*root, &lights, &background, debugger.clone());
+                color += sample;
+                if let Some(features) = features {
+                    hit_samples += 1;
+                    albedo += features.albedo;
+                    normal += features.normal;
+                    depth = depth.min(features.depth);
                 }
             }
-        }
-        let mut pack_rays = SimdVec::new();
-        for chunk in 0..(rays.len() / 64) {
-            if rays.len() < 64 {
-                pack_rays.push_residue(rays.into());
-                break;
-            }
-            let mut array = [Ray {origin: Vector3::new(), dir: Vector3::new(), time: 0.0}; 64];
-            for index in 0..64 {
-                array[index] = rays.pop_front().unwrap();
+            // Albedo and normal average the samples that hit something, so misses don't darken edges
+            if hit_samples > 0 {
+                albedo = albedo * (1.0 / hit_samples as f64);
+                normal = normal.unit();
             }
-            let simd = SimdRay::from_array(array);
-            pack_rays.push(simd);
-        }
-        
-        // for j in 0..(scene.camera.img_height) {
-        //     for i in 0..(scene.camera.img_width) {
-        //         assert_eq!(scene.camera.threadpool.panic_count(), 0);
-        //         let this = scene.camera.clone();
-        //         let progress = pixel_progress.clone();
-        //         let writer = writer.clone();
-        //         let root = scene.root.clone();
-        //         let debugger = debugger.clone();
-        //         scene.camera.threadpool.execute(move || {
-        //             let mut color = Color::BLACK;
-        //             for _ in 0..(this.samples_per_pixel) {
-        //                 let ray = this.get_ray(i, j);
-        //                 color += ray.color(this.max_depth, &*root, debugger.clone());
-        //             }
-        //             let color = color * this.pixel_samples_scale;
-        //             let pixel = Pixel {color, i, j};
-        //             writer.lock().unwrap().write(pixel);
-
-        //             progress.lock().unwrap().inc(1);
-        //         });
-        //     }
-        // }
+            aov.copy_from_slice(&[albedo.r, albedo.g, albedo.b, normal.x(), normal.y(), normal.z(), depth]);
+            color * camera.pixel_samples_scale
+        });
     }
 }
 
-pub struct ScreenUV;
+pub struct ScreenUV {
+    tiles: TileOptions,
+}
 
 impl ScreenUV {
-    pub fn new() -> Box<dyn Renderer> {
-        Box::new(Self{})
+    pub fn new(tiles: TileOptions) -> Box<dyn Renderer> {
+        Box::new(Self{tiles})
     }
 }
 
 impl Renderer for ScreenUV {
     fn render(&self, scene: Scene) {
-        let debugger = Arc::new(Mutex::new(Debugger::new("debug.txt")));
-        let file = File::create(FILE_OUT).unwrap();
-        let writer = Arc::new(Mutex::new(ImgWriter::new(
-            BufWriter::new(file),
-            scene.camera.img_width,
-            scene.camera.img_height,
-        )));
-        for j in 0..(scene.camera.img_height) {
-            for i in 0..(scene.camera.img_width) {
-                writer.lock().unwrap().write(Pixel { color: Color { r: i as f64 / scene.camera.img_width as f64, g: j as f64 / scene.camera.img_height as f64, b: 0.0 }, j, i });
-            }
-        }
-        writer.lock().unwrap().flush();
+        render_tiles(&scene, self.tiles, Vec::new(), |camera, i, j, _aov| {
+            Color { r: i as f64 / camera.img_width as f64, g: j as f64 / camera.img_height as f64, b: 0.0 }
+        });
     }
 }
 
-pub struct UV;
+pub struct UV {
+    tiles: TileOptions,
+}
 
 impl UV {
-    pub fn new() -> Box<dyn Renderer> {
-        Box::new(Self{})
+    pub fn new(tiles: TileOptions) -> Box<dyn Renderer> {
+        Box::new(Self{tiles})
     }
 
-    fn color(ray: &Ray, root: Arc<BVHNode>, debugger: Arc<Mutex<Debugger>>) -> Color {
-        match root.hit(ray, &Interval {min: 0.001, max: f64::INFINITY}) {
+    fn color(hit: &Option<Hit>) -> Color {
+        match hit {
             Some(hit) => {
                 Color {r: hit.u, g: hit.v, b: 0.0}
             }
@@ -117,43 +150,16 @@ impl UV {
 
 impl Renderer for UV {
     fn render(&self, scene: Scene) {
-        let debugger = Arc::new(Mutex::new(Debugger::new("debug.txt")));
-        let file = File::create(FILE_OUT).unwrap();
-        let writer = Arc::new(Mutex::new(ImgWriter::new(
-            BufWriter::new(file),
-            scene.camera.img_width,
-            scene.camera.img_height,
-        )));
-        let pixel_progress = Arc::new(Mutex::new(
-            ProgressBar::new((scene.camera.img_height*scene.camera.img_width) as u64)
-                .with_style(ProgressStyle::with_template("{human_pos}/{len} {wide_bar:.green} {elapsed}").unwrap())
-        ));
-        
-        for j in 0..(scene.camera.img_height) {
-            for i in 0..(scene.camera.img_width) {
-                assert_eq!(scene.camera.threadpool.panic_count(), 0);
-                let this = scene.camera.clone();
-                let progress = pixel_progress.clone();
-                let writer = writer.clone();
-                let root = scene.root.clone();
-                let debugger = debugger.clone();
-                scene.camera.threadpool.execute(move || {
-                    let mut color = Color::BLACK;
-                    for _ in 0..(this.samples_per_pixel) {
-                        let ray = this.get_ray(i, j);
-                        color += Self::color(&ray, root.clone(), debugger.clone());
-                    }
-                    let color = color * this.pixel_samples_scale;
-                    let pixel = Pixel {color, i, j};
-                    writer.lock().unwrap().write(pixel);
-
-                    progress.lock().unwrap().inc(1);
-                });
+        let root = scene.root.clone();
+
+        render_tiles(&scene, self.tiles, Vec::new(), move |camera, i, j, _aov| {
+            // A pixel's samples all start together and go nearly the same way, so they trace well as packets
+            let rays: Vec<Ray> = camera.pixel_rays(i, j).collect();
+            let mut color = Color::BLACK;
+            for hit in root.hit_all(&rays, &Interval {min: 0.001, max: f64::INFINITY}) {
+                color += Self::color(&hit);
             }
-            scene.camera.threadpool.join();
-            
-        }
-        pixel_progress.lock().unwrap().finish();
-        writer.lock().unwrap().flush();
+            color * camera.pixel_samples_scale
+        });
     }
-}
\ No newline at end of file
+}
@@ -1,15 +1,35 @@
 use std::sync::Arc;
 
-use crate::{bvh::BVHNode, camera::Camera, hit::Hittable};
+use crate::{background::Background, camera::Camera, hit::{Hittable, HittableList}};
 
 pub struct Scene {
     pub camera: Arc<Camera>,
-    pub root: Arc<BVHNode>,
+    pub root: Arc<dyn Hittable>,
+    pub background: Arc<Background>,
+    pub lights: Arc<HittableList>,
 }
 
 impl Scene {
-    pub fn new(camera: Camera, root: BVHNode) -> Self {
-        Scene { camera: Arc::new(camera), root: Arc::new(root) }
+    pub fn new(camera: Camera, root: impl Hittable + 'static) -> Self {
+        Scene { camera: Arc::new(camera), root: Arc::new(root), background: Arc::new(Background::SKY), lights: Arc::new(HittableList::new()) }
+    }
+
+    pub fn with_background(mut self, background: Background) -> Self {
+        self.background = Arc::new(background);
+        self
+    }
+
+    // Copies of the emissive objects worth sampling directly
+    pub fn with_lights(mut self, lights: HittableList) -> Self {
+        self.lights = Arc::new(lights);
+        self
+    }
+
+    // Fits the tree to the camera's shutter, so moving objects are only bounded by where they are this frame.
+    // Has to run before rendering hands the root out to other threads
+    pub fn refit(&mut self) {
+        let root = Arc::get_mut(&mut self.root).expect("Can't refit a scene while it's rendering!");
+        root.refit_bounds(&self.camera.shutter);
     }
 
     pub fn objects(&self) -> usize {
@@ -1,52 +1,49 @@
-pub struct SimdVec<S: Simdish> {
-    vec: Vec<S>,
-    residue: Vec<<S as Simdish>::Unpacked>,
-}
-
 pub struct MaskedSimd<S: Simdish> {
     pub simd: S,
     pub mask: <S as Simdish>::Mask,
 }
 
-impl<S: Simdish> SimdVec<S> {
-    pub fn new() -> Self {
-        Self {
-            vec: Vec::new(),
-            residue: Vec::new(),
-        }
-    }
-
-    pub fn push(&mut self, simd: S) {
-        self.vec.push(simd);
-    }
-
-    pub fn push_residue(&mut self, mut residue: Vec<<S as Simdish>::Unpacked>) {
-        self.residue.append(&mut residue);
-    }
-
-    pub fn extract(self) -> (Vec<S>, Vec<<S as Simdish>::Unpacked>) {
-        (self.vec, self.residue)
-    }
-}
-
 impl<S: Simdish> Simdish for MaskedSimd<S> {
     type Unpacked = (<S as Simdish>::Unpacked, bool);
     type Mask = <S as Simdish>::Mask;
+    const LANES: usize = S::LANES;
 
     fn replace(self, replace: Self, mask: <Self as Simdish>::Mask) -> Self {
         let simd = self.simd.replace(replace.simd, mask);
         let mask = self.mask.replace(replace.mask, mask);
         Self { simd, mask }
     }
+
+    fn lane(&self, index: usize) -> Self::Unpacked {
+        (self.simd.lane(index), self.mask.lane(index))
+    }
+
+    fn from_lanes(lanes: &[Self::Unpacked]) -> Self {
+        let simds: Vec<_> = lanes.iter().map(|(simd, _)| *simd).collect();
+        let masks: Vec<_> = lanes.iter().map(|(_, mask)| *mask).collect();
+        Self { simd: S::from_lanes(&simds), mask: S::Mask::from_lanes(&masks) }
+    }
 }
 
 pub trait Simdish {
-    type Unpacked;
+    type Unpacked: Copy;
     type Mask: Maskish;
+    const LANES: usize;
 
+    // Takes the lanes of `replace` where `mask` is set
     fn replace(self, replace: Self, mask: <Self as Simdish>::Mask) -> Self;
+
+    fn lane(&self, index: usize) -> Self::Unpacked;
+
+    // Needs exactly LANES items
+    fn from_lanes(lanes: &[Self::Unpacked]) -> Self;
 }
 
 pub trait Maskish: Copy + Clone {
     fn replace(self, replace: Self, mask: Self) -> Self;
-}
\ No newline at end of file
+
+    // Whether every part of the lane is set
+    fn lane(&self, index: usize) -> bool;
+
+    fn from_lanes(lanes: &[bool]) -> Self;
+}
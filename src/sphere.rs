@@ -1,11 +1,14 @@
 use std::f64::consts::{PI, TAU};
 use std::sync::Arc;
 
+use rand::random;
+
 use crate::aabb::AABB;
 use crate::anim::Animation;
 use crate::hit::{Hit, Hittable};
 use crate::interval::Interval;
 use crate::material::Material;
+use crate::onb::Onb;
 use crate::ray::Ray;
 use crate::vector::{Point3, Vector3};
 
@@ -49,7 +52,7 @@ impl Sphere {
         Box::new(self)
     }
 
-    fn get_sphere_uv(p: Point3) -> (f64, f64) {
+    pub fn get_sphere_uv(p: Point3) -> (f64, f64) {
         let theta = (-p.y()).acos();
         let phi = (-p.z()).atan2(p.x()) + PI;
 
@@ -95,6 +98,7 @@ impl Hittable for Sphere {
             u,
             v,
             front_face,
+            color: None,
             material: self.material.clone()
         });
     }
@@ -102,4 +106,41 @@ impl Hittable for Sphere {
     fn bounding(&self) -> &AABB {
         &self.bbox
     }
+
+    fn refit_bounds(&mut self, time: &Interval) -> AABB {
+        self.bbox = self.anim.bound_interval(*time);
+        self.bbox
+    }
+
+    fn pdf_value(&self, origin: &Point3, dir: &Vector3, time: f64) -> f64 {
+        let ray = Ray {origin: *origin, dir: *dir, time};
+        if self.hit(&ray, &Interval {min: 0.001, max: f64::INFINITY}).is_none() {
+            return 0.0;
+        }
+
+        let dist_squared = (self.anim.sample(time) - *origin).length_squared();
+        let radius_squared = self.radius * self.radius;
+        if dist_squared <= radius_squared {return 0.0;}
+
+        let cos_theta_max = (1.0 - radius_squared / dist_squared).sqrt();
+        let solid_angle = TAU * (1.0 - cos_theta_max);
+        1.0 / solid_angle
+    }
+
+    fn random(&self, origin: &Point3, time: f64) -> Vector3 {
+        // Uniform over the cone of directions the sphere covers
+        let dir = self.anim.sample(time) - *origin;
+        let dist_squared = dir.length_squared();
+        let radius_squared = self.radius * self.radius;
+        if dist_squared <= radius_squared {return Vector3::random_unit();}
+
+        let cos_theta_max = (1.0 - radius_squared / dist_squared).sqrt();
+        let r1: f64 = random();
+        let r2: f64 = random();
+        let z = 1.0 + r2 * (cos_theta_max - 1.0);
+        let phi = TAU * r1;
+        let sin_theta = (1.0 - z * z).sqrt();
+
+        Onb::new(&dir).transform(&Vector3(phi.cos() * sin_theta, phi.sin() * sin_theta, z))
+    }
 }
\ No newline at end of file
@@ -36,19 +36,28 @@ impl LinearSpline {
 
 impl Curve for LinearSpline {
     fn sample(&self, u: f64) -> Point3 {
-        let segment = u.floor() as usize;
-        let t = u.fract();
-        lerp(&self.controls[segment], &self.controls[segment + 1], t)
+        let len = self.controls.len();
+        if len == 1 {return self.controls[0];}
+
+        // u == len - 1 is the last control, not the start of a segment past it
+        let u = Interval {min: 0.0, max: (len - 1) as f64}.clamp(u);
+        let segment = (u.floor() as usize).min(len - 2);
+        lerp(&self.controls[segment], &self.controls[segment + 1], u - segment as f64)
     }
 }
 
 impl BoundedCurve for LinearSpline {
+    // Where the curve is at either end, plus every control it passes in between
     fn bound_interval(&self, bound: Interval) -> AABB {
-        let len = self.controls.len();
-        let len_interval = Interval {min: 0.0, max: (len - 2) as f64};
-        let segment_min = len_interval.clamp(bound.min) as usize;
-        let segment_max = len_interval.clamp(bound.max) as usize;
-        AABB::enclose(&self.bound_segment(segment_min), &self.bound_segment(segment_max))
+        let len_interval = Interval {min: 0.0, max: (self.controls.len() - 1) as f64};
+        let min = len_interval.clamp(bound.min);
+        let max = len_interval.clamp(bound.max);
+
+        let mut bbox = AABB::from(self.sample(min), self.sample(max));
+        for control in self.controls.iter().take(max.ceil() as usize).skip(min.floor() as usize + 1) {
+            bbox = AABB::enclose(&bbox, &AABB::from(*control, *control));
+        }
+        bbox
     }
 }
 
@@ -0,0 +1,42 @@
+use std::{collections::HashMap, fs, sync::Arc};
+
+use crate::{material::Material, mesh::Mesh, triangle_mesh::TriangleMesh, vector::Vector3};
+
+const HEADER: usize = 80;
+const FACET: usize = 50;
+
+pub fn load_stl(path: &str, material: Arc<Box<dyn Material>>) -> Result<TriangleMesh, String> {
+    let data = fs::read(path).map_err(|err| format!("Couldn't read {}: {}", path, err))?;
+    Ok(TriangleMesh::new(read_stl(&data)?, material))
+}
+
+// Binary STL only. Facets repeat their corners, so identical positions are merged back into shared vertices
+pub fn read_stl(data: &[u8]) -> Result<Mesh, String> {
+    if data.len() < HEADER + 4 {
+        return Err(String::from("This STL file is too short to be binary STL!"));
+    }
+    let count = u32::from_le_bytes(data[HEADER..HEADER + 4].try_into().unwrap()) as usize;
+    if data.len() < HEADER + 4 + count * FACET {
+        return Err(format!("This STL file is too short for {} facets, is it ASCII STL?", count));
+    }
+
+    let float = |offset: usize| f32::from_le_bytes(data[offset..offset + 4].try_into().unwrap());
+
+    let mut mesh = Mesh::new();
+    let mut shared: HashMap<[u32; 3], usize> = HashMap::new();
+    for facet in 0..count {
+        // Skip the facet normal, we use the winding instead
+        let start = HEADER + 4 + facet * FACET + 12;
+        let mut face = [0; 3];
+        for (corner, index) in face.iter_mut().enumerate() {
+            let offset = start + corner * 12;
+            let (x, y, z) = (float(offset), float(offset + 4), float(offset + 8));
+            *index = *shared.entry([x.to_bits(), y.to_bits(), z.to_bits()]).or_insert_with(|| {
+                mesh.positions.push(Vector3(x as f64, y as f64, z as f64));
+                mesh.positions.len() - 1
+            });
+        }
+        mesh.indices.push(face);
+    }
+    Ok(mesh)
+}
@@ -1,6 +1,909 @@
-use crate::{math::lerp, vector::Vector3};
+use std::{f64::consts::PI, path::Path, sync::{Arc, Mutex}, time::Duration};
+
+use png::{BitDepth, ColorType};
+use threadpool::ThreadPool;
+
+use crate::{anim::Animation, background::Background, bvh::{BVHBuilder, BVHNode, BVHStats, SahOptions}, bvh4::Bvh4, camera::Camera, cone::Cone, cylinder::Cylinder, color::{Color, Pixel}, encoder::{ImageEncoder, P6Encoder, PgmEncoder}, exr_writer::{write_exr, ExrCompression}, framebuffer::{FrameBuffer, Layer}, hdr_writer::write_hdr, hit::{Hittable, HittableList}, image::read_img, interval::{Interval, SimdInterval, SimdIntervalMask}, linear_bvh::LinearBVH, material::{DiffuseLight, Lambertian, Material}, math::{lerp, solve_quartic}, matrix::Mat4, mesh::Mesh, obj::{load_obj, read_obj_groups}, ply::read_ply, spline::{BoundedCurve, Curve, LinearSpline}, plane::Plane, png_writer::write_png, quad::Quad, quality::QualityOptions, ray::Ray, scene::Scene, simd::{Maskish, Simdish}, sphere::Sphere, stl::read_stl, texture::{ImageTexture, Texture}, tile::{TileOptions, TileOrder, TileScheduler}, tlas::Tlas, torus::Torus, transform::Transformed, triangle::Triangle, triangle_mesh::TriangleMesh, vector::Vector3, writer::Debugger};
 
 #[test]
 fn lerp_test() {
     assert_eq!(lerp(&Vector3(1.0, 1.0, 1.0), &Vector3(3.0, 3.0, 3.0), 0.5), Vector3(2.0, 2.0, 2.0))
-}
\ No newline at end of file
+}
+
+#[test]
+fn tile_orders_cover_image() {
+    for order in [TileOrder::Scanline, TileOrder::Spiral, TileOrder::Hilbert] {
+        let tiles = TileScheduler::tiles(100, 37, TileOptions::new(16, order));
+        assert_eq!(tiles.len(), 7 * 3);
+        assert_eq!(tiles.iter().map(|tile| tile.area()).sum::<usize>(), 100 * 37);
+        for (n, tile) in tiles.iter().enumerate() {
+            assert!(!tiles[..n].contains(tile));
+        }
+    }
+}
+
+#[test]
+fn framebuffer_partial_readback() {
+    let frame = FrameBuffer::new(4, 2);
+    frame.write(Pixel {color: Color {r: 0.5, g: 0.25, b: 1.0}, i: 3, j: 1});
+    assert_eq!(frame.written(), 1);
+    assert!(!frame.is_complete());
+
+    let snapshot = frame.snapshot();
+    assert_eq!(snapshot.len(), 8);
+    assert_eq!(snapshot[7].g, 0.25);
+    assert_eq!(snapshot[0].r, 0.0);
+}
+
+#[test]
+fn png_output_is_srgb() {
+    let frame = FrameBuffer::new(2, 2);
+    frame.write(Pixel {color: Color::WHITE, i: 1, j: 0});
+    let mut data = Vec::new();
+    write_png(&mut data, &frame, BitDepth::Sixteen);
+
+    let mut reader = png::Decoder::new(&data[..]).read_info().unwrap();
+    assert!(reader.info().srgb.is_some());
+    assert!(reader.info().source_gamma.is_some());
+    let mut buf = vec![0; reader.output_buffer_size()];
+    reader.next_frame(&mut buf).unwrap();
+    assert_eq!(&buf[..12], &[0, 0, 0, 0, 0, 0, 255, 255, 255, 255, 255, 255]);
+}
+
+#[test]
+fn image_texture_samples_rgba_and_non_square() {
+    // 3x2, so indexing by width instead of height and 4 channels instead of 3 both matter
+    for (color_type, samples) in [(ColorType::Rgb, 3), (ColorType::Rgba, 4)] {
+        let mut pixels = Vec::new();
+        for y in 0..2_u8 {
+            for x in 0..3_u8 {
+                pixels.extend_from_slice(&[50 * x, 100 * y, 7, 255][..samples]);
+            }
+        }
+        let mut data = Vec::new();
+        let mut encoder = png::Encoder::new(&mut data, 3, 2);
+        encoder.set_color(color_type);
+        encoder.write_header().unwrap().write_image_data(&pixels).unwrap();
+
+        let texture = ImageTexture::from_img(read_img(&data[..]));
+        let origin = Vector3(0.0, 0.0, 0.0);
+        for y in 0..2 {
+            for x in 0..3 {
+                // v runs bottom to top, rows top to bottom
+                let (u, v) = ((x as f64 + 0.5) / 3.0, 1.0 - (y as f64 + 0.5) / 2.0);
+                let color = texture.value(u, v, &origin, debugger());
+                assert_eq!((color.r, color.g, color.b), ((50 * x) as f64 / 256.0, (100 * y) as f64 / 256.0, 7.0 / 256.0));
+            }
+        }
+        // The far edges stay on the last pixel
+        assert_eq!(texture.value(1.0, 0.0, &origin, debugger()).r, 100.0 / 256.0);
+    }
+}
+
+#[test]
+fn image_texture_samples_grey_and_16_bit() {
+    let encode = |color_type, bit_depth, pixels: &[u8]| {
+        let mut data = Vec::new();
+        let mut encoder = png::Encoder::new(&mut data, 2, 1);
+        encoder.set_color(color_type);
+        encoder.set_depth(bit_depth);
+        encoder.write_header().unwrap().write_image_data(pixels).unwrap();
+        ImageTexture::from_img(read_img(&data[..]))
+    };
+    let origin = Vector3(0.0, 0.0, 0.0);
+    let rgb = |texture: &ImageTexture, u| {
+        let color = texture.value(u, 0.5, &origin, debugger());
+        (color.r, color.g, color.b)
+    };
+
+    let grey = encode(ColorType::Grayscale, BitDepth::Eight, &[64, 128]);
+    assert_eq!(rgb(&grey, 0.75), (0.5, 0.5, 0.5));
+    let grey_alpha = encode(ColorType::GrayscaleAlpha, BitDepth::Eight, &[64, 255, 128, 255]);
+    assert_eq!(rgb(&grey_alpha, 0.75), (0.5, 0.5, 0.5));
+    // Two bits a pixel, 01 and 11, which come out as 85 and 255
+    let packed = encode(ColorType::Grayscale, BitDepth::Two, &[0b0111_0000]);
+    assert_eq!(rgb(&packed, 0.25), (85.0 / 256.0, 85.0 / 256.0, 85.0 / 256.0));
+    let deep = encode(ColorType::Rgb, BitDepth::Sixteen, &[0, 0, 0, 0, 0, 0, 0x80, 0, 0x40, 0, 0x20, 0]);
+    assert_eq!(rgb(&deep, 0.75), (0.5, 0.25, 0.125));
+}
+
+// Undoes write_exr's RLE and predictor
+fn unpack_exr_rle(packed: &[u8]) -> Vec<u8> {
+    let mut data = Vec::new();
+    let mut at = 0;
+    while at < packed.len() {
+        let count = packed[at] as i8;
+        if count < 0 {
+            data.extend_from_slice(&packed[(at + 1)..(at + 1 + (-count) as usize)]);
+            at += 1 + (-count) as usize;
+        } else {
+            data.extend(std::iter::repeat_n(packed[at + 1], count as usize + 1));
+            at += 2;
+        }
+    }
+    for k in 1..data.len() {
+        data[k] = data[k - 1].wrapping_add(data[k]).wrapping_sub(128);
+    }
+    let half = data.len().div_ceil(2);
+    (0..data.len()).map(|k| if k % 2 == 0 {data[k / 2]} else {data[half + k / 2]}).collect()
+}
+
+#[test]
+fn exr_rle_round_trips() {
+    // Wide enough that one scanline is a run of more than 127 identical bytes
+    let (width, height) = (64, 3);
+    let beauty = Layer::beauty(width, height);
+    let depth = Layer::new("depth", &["Z"], width, height);
+    for j in 0..height {
+        for i in 0..width {
+            beauty.buffer.write(Pixel {color: Color {r: 0.5, g: 0.25, b: j as f64}, i, j});
+            depth.buffer.write_values(i, j, &[i as f64]);
+        }
+    }
+    let mut data = Vec::new();
+    write_exr(&mut data, &[beauty, depth], ExrCompression::Rle);
+
+    assert_eq!(&data[..8], &[0x76, 0x2f, 0x31, 0x01, 2, 0, 0, 0]);
+    let find = |needle: &[u8]| data.windows(needle.len()).position(|window| window == needle).unwrap();
+    assert_eq!(data[find(b"compression\0compression\0") + 24..][..5], [1, 0, 0, 0, 1]);
+    assert!(find(b"B\0") < find(b"G\0") && find(b"G\0") < find(b"R\0") && find(b"R\0") < find(b"depth.Z\0"));
+
+    // The header ends with an empty attribute name, then one offset per scanline
+    let table = find(b"screenWindowWidth\0float\0") + 24 + 4 + 4 + 1;
+    let offsets: Vec<usize> = (0..height).map(|j| u64::from_le_bytes(data[(table + 8 * j)..][..8].try_into().unwrap()) as usize).collect();
+    assert_eq!(offsets[0], table + 8 * height);
+    for j in 0..height {
+        let at = offsets[j];
+        assert_eq!(i32::from_le_bytes(data[at..][..4].try_into().unwrap()), j as i32);
+        let size = i32::from_le_bytes(data[(at + 4)..][..4].try_into().unwrap()) as usize;
+        let end = if j + 1 < height {offsets[j + 1]} else {data.len()};
+        assert_eq!(at + 8 + size, end);
+
+        let packed = &data[(at + 8)..end];
+        assert!(size < width * 4 * 4);
+        assert!(packed.contains(&127));
+        let raw = unpack_exr_rle(packed);
+        let value = |channel: usize, i: usize| f32::from_le_bytes(raw[(4 * (channel * width + i))..][..4].try_into().unwrap());
+        assert_eq!((value(0, 5), value(1, 5), value(2, 5)), (j as f32, 0.25, 0.5));
+        assert_eq!(value(3, 40), 40.0);
+    }
+}
+
+// Splits a binary PNM file into its header fields and pixel data
+fn read_pnm(data: &[u8]) -> (Vec<String>, &[u8]) {
+    let mut fields = Vec::new();
+    let mut at = 0;
+    while fields.len() < 4 {
+        let end = at + data[at..].iter().position(|byte| byte.is_ascii_whitespace()).unwrap();
+        fields.push(String::from_utf8(data[at..end].to_vec()).unwrap());
+        at = end + 1;
+    }
+    (fields, &data[at..])
+}
+
+#[test]
+fn p6_output_round_trips() {
+    let layers = [Layer::beauty(3, 2)];
+    layers[0].buffer.write(Pixel {color: Color::WHITE, i: 2, j: 0});
+    layers[0].buffer.write(Pixel {color: Color {r: 0.25, g: 0.0, b: 1.0}, i: 0, j: 1});
+    let mut data = Vec::new();
+    P6Encoder.encode(&mut data, &layers);
+
+    let (header, pixels) = read_pnm(&data);
+    assert_eq!(header, ["P6", "3", "2", "255"]);
+    assert_eq!(pixels.len(), 3 * 2 * 3);
+    assert_eq!(&pixels[6..9], &[255, 255, 255]);
+    assert_eq!(&pixels[9..12], &[128, 0, 255]);
+}
+
+#[test]
+fn pgm_output_round_trips() {
+    let (width, height) = (4, 1);
+    let layers = [Layer::beauty(width, height), Layer::new("depth", &["Z"], width, height)];
+    for (i, depth) in [2.0, 4.0, 6.0, f64::INFINITY].into_iter().enumerate() {
+        layers[0].buffer.write(Pixel {color: Color::from_all(i as f64 / 3.0), i, j: 0});
+        layers[1].buffer.write_values(i, 0, &[depth]);
+    }
+    let decode = |pixels: &[u8]| pixels.chunks(2).map(|pair| u16::from_be_bytes([pair[0], pair[1]])).collect::<Vec<u16>>();
+
+    let mut data = Vec::new();
+    PgmEncoder::new("depth").encode(&mut data, &layers);
+    let (header, pixels) = read_pnm(&data);
+    assert_eq!(header, ["P5", "4", "1", "65535"]);
+    assert_eq!(decode(pixels), [0, 32768, 65535, 65535]);
+
+    // Without a depth layer, e.g. from the UV renderers, it writes the beauty pass's luminance
+    let mut data = Vec::new();
+    PgmEncoder::new("depth").encode(&mut data, &layers[..1]);
+    let (_, pixels) = read_pnm(&data);
+    let values = decode(pixels);
+    assert_eq!((values[0], values[3]), (0, 65535));
+    assert!(values[1] < values[2]);
+}
+
+#[test]
+fn hdr_output_keeps_radiance() {
+    let rgbe = |color| {
+        let frame = FrameBuffer::new(1, 1);
+        frame.write(Pixel {color, i: 0, j: 0});
+        let mut data = Vec::new();
+        write_hdr(&mut data, &frame);
+        data[data.len() - 4..].to_vec()
+    };
+    assert_eq!(rgbe(Color {r: 4.0, g: 2.0, b: 1.0}), [128, 64, 32, 131]);
+    // Past what the exponent byte holds: too bright saturates, too dim is black
+    assert_eq!(rgbe(Color {r: 1e300, g: 0.0, b: 0.0}), [255, 0, 0, 255]);
+    assert_eq!(rgbe(Color::from_all(1e-60)), [0, 0, 0, 0]);
+}
+
+#[test]
+fn triangle_hit_interpolates() {
+    let material = Lambertian::from_const_col(Color::WHITE).to_dyn();
+    let triangle = Triangle::new(
+        Vector3(0.0, 0.0, 0.0),
+        Vector3(1.0, 0.0, 0.0),
+        Vector3(0.0, 1.0, 0.0),
+        material,
+    ).with_uvs([(0.0, 0.0), (1.0, 0.0), (0.0, 1.0)]);
+
+    let ray = Ray {origin: Vector3(0.25, 0.5, 1.0), dir: Vector3(0.0, 0.0, -1.0), time: 0.0};
+    let hit = triangle.hit(&ray, &Interval {min: 0.001, max: f64::INFINITY}).unwrap();
+    assert!((hit.t - 1.0).abs() < 1e-12);
+    assert!((hit.u - 0.25).abs() < 1e-12 && (hit.v - 0.5).abs() < 1e-12);
+    assert!(hit.front_face);
+    assert_eq!(hit.normal, Vector3(0.0, 0.0, 1.0));
+
+    let miss = Ray {origin: Vector3(0.75, 0.5, 1.0), dir: Vector3(0.0, 0.0, -1.0), time: 0.0};
+    assert!(triangle.hit(&miss, &Interval {min: 0.001, max: f64::INFINITY}).is_none());
+}
+
+#[test]
+fn obj_faces_are_triangulated() {
+    let text = "v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\ng quad\nf 1 2 3 4\ng tri\nusemtl missing\nf -4 -3 -1\n";
+    let (groups, warnings) = read_obj_groups(text.as_bytes(), "faces.obj", Path::new("")).unwrap();
+
+    assert_eq!(warnings, vec![String::from("faces.obj:8: Unknown material missing, using the default")]);
+    assert_eq!(groups.len(), 2);
+    assert_eq!((groups[0].0.as_str(), groups[0].1.num_triangles()), ("quad", 2));
+    assert_eq!((groups[1].0.as_str(), groups[1].1.num_triangles()), ("tri", 1));
+
+    let ray = Ray {origin: Vector3(0.25, 0.75, 1.0), dir: Vector3(0.0, 0.0, -1.0), time: 0.0};
+    assert!(groups[0].1.hit(&ray, &Interval {min: 0.001, max: f64::INFINITY}).is_some());
+    assert!(groups[1].1.hit(&ray, &Interval {min: 0.001, max: f64::INFINITY}).is_some());
+}
+
+#[test]
+fn obj_reports_bad_files() {
+    let text = "mtllib nowhere.mtl\nv 0 0 0\nv 1 0 0\nv 1 1 0\nf 1 2 3\n";
+    let (groups, warnings) = read_obj_groups(text.as_bytes(), "lib.obj", Path::new("")).unwrap();
+    assert_eq!(groups.len(), 1);
+    assert_eq!(warnings, vec![String::from("lib.obj:1: Couldn't read nowhere.mtl, using the default material")]);
+
+    let text = "v 0 0 0\nv 1 zero 0\n";
+    assert_eq!(read_obj_groups(text.as_bytes(), "bad.obj", Path::new("")).err(), Some(String::from("bad.obj:2: zero isn't a number!")));
+    assert!(load_obj("nowhere.obj").is_err());
+}
+
+#[test]
+fn ply_reads_vertex_colors() {
+    let header = "ply\nformat binary_little_endian 1.0\nelement vertex 4\nproperty float x\nproperty float y\nproperty float z\nproperty uchar red\nproperty uchar green\nproperty uchar blue\nelement face 1\nproperty list uchar int vertex_indices\nend_header\n";
+    let mut data = header.as_bytes().to_vec();
+    for (x, y, red) in [(0.0_f32, 0.0_f32, 255_u8), (1.0, 0.0, 0), (1.0, 1.0, 0), (0.0, 1.0, 51)] {
+        for value in [x, y, 0.0] {
+            data.extend_from_slice(&value.to_le_bytes());
+        }
+        data.extend_from_slice(&[red, 0, 0]);
+    }
+    data.push(4);
+    for index in 0..4_i32 {
+        data.extend_from_slice(&index.to_le_bytes());
+    }
+
+    let mesh = read_ply(&data).unwrap();
+    assert_eq!(mesh.indices, vec![[0, 1, 2], [0, 2, 3]]);
+    assert_eq!(mesh.positions[2], Vector3(1.0, 1.0, 0.0));
+    let colors = mesh.colors.as_ref().unwrap();
+    assert_eq!((colors[0].r, colors[3].r), (1.0, 0.2));
+
+    // Halfway up the left edge, a hundredth in: half of corner 0, 0.49 of corner 3 and 0.01 of corner 2
+    let ray = Ray {origin: Vector3(0.01, 0.5, 1.0), dir: Vector3(0.0, 0.0, -1.0), time: 0.0};
+    let hit = TriangleMesh::from_vertex_colors(mesh).hit(&ray, &Interval {min: 0.0, max: f64::INFINITY}).unwrap();
+    assert!((hit.material.albedo(&hit, debugger()).r - 0.598).abs() < 1e-9);
+}
+
+#[test]
+fn ply_rejects_bad_faces() {
+    let header = "ply\nformat ascii 1.0\nelement vertex 3\nproperty float x\nproperty float y\nproperty float z\nelement face 1\nproperty list char int vertex_indices\nend_header\n0 0 0\n1 0 0\n1 1 0\n";
+    assert!(read_ply(format!("{}3 0 1 2\n", header).as_bytes()).is_ok());
+    assert!(read_ply(format!("{}3 0 1 3\n", header).as_bytes()).is_err());
+    assert!(read_ply(format!("{}3 0 -1 2\n", header).as_bytes()).is_err());
+    assert!(read_ply(format!("{}-3 0 1 2\n", header).as_bytes()).is_err());
+}
+
+#[test]
+fn stl_merges_shared_corners() {
+    let mut data = vec![0; 80];
+    data.extend_from_slice(&2_u32.to_le_bytes());
+    for corners in [[[0.0_f32, 0.0, 0.0], [1.0, 0.0, 0.0], [1.0, 1.0, 0.0]], [[0.0, 0.0, 0.0], [1.0, 1.0, 0.0], [0.0, 1.0, 0.0]]] {
+        data.extend_from_slice(&[0; 12]);
+        for value in corners.iter().flatten() {
+            data.extend_from_slice(&value.to_le_bytes());
+        }
+        data.extend_from_slice(&[0; 2]);
+    }
+
+    let mesh = read_stl(&data).unwrap();
+    assert_eq!(mesh.positions.len(), 4);
+    assert_eq!(mesh.indices, vec![[0, 1, 2], [0, 2, 3]]);
+}
+
+#[test]
+fn triangle_mesh_matches_triangles() {
+    // A bumpy 16x16 grid, big enough for the mesh to build a few levels of BVH
+    let mut mesh = Mesh::new();
+    for j in 0..=16 {
+        for i in 0..=16 {
+            let (x, z) = (i as f64 / 16.0, j as f64 / 16.0);
+            mesh.positions.push(Vector3(x, 0.1 * (7.0 * x).sin() * (5.0 * z).cos(), z));
+        }
+    }
+    for j in 0..16 {
+        for i in 0..16 {
+            let corner = j * 17 + i;
+            mesh.indices.push([corner, corner + 1, corner + 18]);
+            mesh.indices.push([corner, corner + 18, corner + 17]);
+        }
+    }
+
+    let material = Lambertian::from_const_col(Color::WHITE).to_dyn();
+    let mut triangles = HittableList::new();
+    for &[a, b, c] in &mesh.indices {
+        triangles.add(Triangle::new(mesh.positions[a], mesh.positions[b], mesh.positions[c], material.clone()).as_box());
+    }
+    let triangle_mesh = TriangleMesh::new(mesh, material);
+    assert_eq!(triangle_mesh.objects(), 512);
+
+    let ray_t = Interval {min: 0.001, max: f64::INFINITY};
+    for k in 0..100 {
+        let origin = Vector3(0.5, 2.0, 0.5);
+        let target = Vector3((k % 10) as f64 / 9.0 * 1.2 - 0.1, 0.0, (k / 10) as f64 / 9.0 * 1.2 - 0.1);
+        let ray = Ray {origin, dir: target - origin, time: 0.0};
+        match (triangles.hit(&ray, &ray_t), triangle_mesh.hit(&ray, &ray_t)) {
+            (None, None) => {},
+            (Some(a), Some(b)) => assert!((a.t - b.t).abs() < 1e-12),
+            _ => panic!("Ray {} hit only one of them!", k),
+        }
+    }
+}
+
+#[test]
+fn plane_stays_outside_bvh() {
+    let material = Lambertian::from_const_col(Color::WHITE).to_dyn();
+    let objects = vec![
+        Plane::new(Vector3(0.0, 0.0, 0.0), Vector3(0.0, 1.0, 0.0), material.clone()).as_box(),
+        Quad::new(Vector3(-1.0, 1.0, -1.0), Vector3(2.0, 0.0, 0.0), Vector3(0.0, 0.0, 2.0), material.clone()).as_box(),
+        Sphere::from_const_pos(10.0, 1.0, 0.0, 1.0, material.clone()).as_box(),
+        Sphere::from_const_pos(-10.0, 1.0, 0.0, 1.0, material).as_box(),
+    ];
+    let root = BVHNode::new(objects, None);
+    assert!(!root.bounding().is_bounded());
+    assert_eq!(root.objects(), 4);
+
+    let ray_t = Interval {min: 0.001, max: f64::INFINITY};
+    let down = |x: f64, z: f64| Ray {origin: Vector3(x, 5.0, z), dir: Vector3(0.0, -1.0, 0.0), time: 0.0};
+    let quad_hit = root.hit(&down(0.5, -0.5), &ray_t).unwrap();
+    assert!((quad_hit.t - 4.0).abs() < 1e-12);
+    assert!((quad_hit.u - 0.75).abs() < 1e-12 && (quad_hit.v - 0.25).abs() < 1e-12);
+    assert!((root.hit(&down(1000.0, 1000.0), &ray_t).unwrap().t - 5.0).abs() < 1e-12);
+    assert!((root.hit(&down(10.0, 0.0), &ray_t).unwrap().t - 3.0).abs() < 1e-12);
+}
+
+#[test]
+fn analytic_shapes_hit() {
+    let roots = solve_quartic(2.0, -20.0, 70.0, -100.0, 48.0);
+    assert_eq!(roots.len(), 4);
+    for (root, expected) in roots.iter().zip([1.0, 2.0, 3.0, 4.0]) {
+        assert!((root - expected).abs() < 1e-9);
+    }
+
+    let material = Lambertian::from_const_col(Color::WHITE).to_dyn();
+    let ray_t = Interval {min: 0.001, max: f64::INFINITY};
+    let ray = Ray {origin: Vector3(0.0, 0.0, 10.0), dir: Vector3(0.0, 0.0, -2.0), time: 0.0};
+
+    // Straight through the middle of the tube on the near side, then out of the hole
+    let torus = Torus::new(Vector3(0.0, 0.0, 0.0), Vector3(0.0, 1.0, 0.0), 2.0, 0.5, material.clone());
+    let hit = torus.hit(&ray, &ray_t).unwrap();
+    assert!((hit.t - 3.75).abs() < 1e-9);
+    assert!((hit.normal - Vector3(0.0, 0.0, 1.0)).length() < 1e-9);
+    let inside = torus.hit(&ray, &Interval {min: 3.8, max: f64::INFINITY}).unwrap();
+    assert!((inside.t - 4.25).abs() < 1e-9 && !inside.front_face);
+
+    // Down onto the top cap
+    let cylinder = Cylinder::new(Vector3(0.0, 0.0, 0.0), Vector3(0.0, 0.0, 3.0), 1.0, material.clone());
+    let hit = cylinder.hit(&ray, &ray_t).unwrap();
+    assert!((hit.t - 3.5).abs() < 1e-9 && hit.normal == Vector3(0.0, 0.0, 1.0));
+    let open = Cylinder::new(Vector3(0.0, 0.0, 0.0), Vector3(0.0, 0.0, 3.0), 1.0, material.clone()).with_caps(false);
+    assert!(open.hit(&ray, &ray_t).is_none());
+
+    let side = Ray {origin: Vector3(5.0, 0.0, 1.0), dir: Vector3(-1.0, 0.0, 0.0), time: 0.0};
+    let cone = Cone::new(Vector3(0.0, 0.0, 0.0), Vector3(0.0, 0.0, 2.0), 1.0, material);
+    assert!((cone.hit(&side, &ray_t).unwrap().t - 4.5).abs() < 1e-9);
+}
+
+#[test]
+fn transformed_sphere_is_an_ellipsoid() {
+    let matrix = Mat4::translate(Vector3(0.0, 3.0, 0.0))
+        * Mat4::rotate(Vector3(0.0, 0.0, 1.0), 90.0)
+        * Mat4::scale(Vector3(2.0, 1.0, 1.0));
+    let product = matrix * matrix.inverse();
+    for row in 0..4 {
+        for col in 0..4 {
+            assert!((product.m[row][col] - Mat4::IDENTITY.m[row][col]).abs() < 1e-12);
+        }
+    }
+
+    // Stretched along x, then turned so the long axis points up
+    let material = Lambertian::from_const_col(Color::WHITE).to_dyn();
+    let sphere: Arc<Box<dyn Hittable>> = Arc::new(Sphere::from_const_pos(0.0, 0.0, 0.0, 1.0, material).as_box());
+    let ellipsoid = Transformed::new(sphere, matrix);
+    let bbox = ellipsoid.bounding();
+    assert!((bbox.y.min - 1.0).abs() < 1e-9 && (bbox.y.max - 5.0).abs() < 1e-9);
+    assert!((bbox.x.max - 1.0).abs() < 1e-9);
+
+    let ray_t = Interval {min: 0.001, max: f64::INFINITY};
+    let down = Ray {origin: Vector3(0.0, 10.0, 0.0), dir: Vector3(0.0, -1.0, 0.0), time: 0.0};
+    let hit = ellipsoid.hit(&down, &ray_t).unwrap();
+    assert!((hit.t - 5.0).abs() < 1e-9);
+    assert!((hit.normal - Vector3(0.0, 1.0, 0.0)).length() < 1e-9);
+
+    // Off-centre the normal leans outwards more than a sphere's would
+    let side = Ray {origin: Vector3(0.5, 10.0, 0.0), dir: Vector3(0.0, -1.0, 0.0), time: 0.0};
+    let hit = ellipsoid.hit(&side, &ray_t).unwrap();
+    let expected = Vector3(0.5, (1.0 - 0.25_f64).sqrt() / 2.0, 0.0).unit();
+    assert!((hit.normal - expected).length() < 1e-9);
+}
+
+#[test]
+fn tlas_follows_moved_instances() {
+    let material = Lambertian::from_const_col(Color::WHITE).to_dyn();
+    let sphere: Arc<Box<dyn Hittable>> = Arc::new(Sphere::from_const_pos(0.0, 0.0, 0.0, 1.0, material).as_box());
+    let instances = (0..8)
+        .map(|k| Transformed::new(sphere.clone(), Mat4::translate(Vector3(3.0 * k as f64, 0.0, 0.0))))
+        .collect();
+    let mut tlas = Tlas::new(instances);
+    assert_eq!(tlas.objects(), 8);
+
+    let ray_t = Interval {min: 0.001, max: f64::INFINITY};
+    let down = |x: f64| Ray {origin: Vector3(x, 10.0, 0.0), dir: Vector3(0.0, -1.0, 0.0), time: 0.0};
+    assert!((tlas.hit(&down(6.0), &ray_t).unwrap().t - 9.0).abs() < 1e-9);
+    assert!(tlas.hit(&down(-10.0), &ray_t).is_none());
+
+    // Lift the third sphere and send it far off to the side
+    tlas.set_transform(2, Mat4::translate(Vector3(-10.0, 5.0, 0.0)));
+    tlas.refit();
+    assert!(tlas.hit(&down(6.0), &ray_t).is_none());
+    assert!((tlas.hit(&down(-10.0), &ray_t).unwrap().t - 4.0).abs() < 1e-9);
+    assert_eq!(tlas.bounding().x.min, -11.0);
+
+    tlas.rebuild();
+    assert!((tlas.hit(&down(-10.0), &ray_t).unwrap().t - 4.0).abs() < 1e-9);
+    assert!((tlas.hit(&down(21.0), &ray_t).unwrap().t - 9.0).abs() < 1e-9);
+}
+
+#[test]
+fn sah_beats_median_on_clusters() {
+    // A tight clump of small spheres next to a few big ones spread far apart
+    let material = Lambertian::from_const_col(Color::WHITE).to_dyn();
+    let spheres = || {
+        let mut objects = Vec::new();
+        for k in 0..200 {
+            let (x, y, z) = ((k % 6) as f64 * 0.3, (k / 6 % 6) as f64 * 0.3, (k / 36) as f64 * 0.3);
+            objects.push(Sphere::from_const_pos(x, y, z, 0.1, material.clone()).as_box());
+        }
+        for k in 0..8 {
+            objects.push(Sphere::from_const_pos(40.0 * k as f64 - 150.0, 20.0, 0.0, 5.0, material.clone()).as_box());
+        }
+        objects
+    };
+
+    let median = BVHNode::new(spheres(), None);
+    let sah = BVHNode::with_builder(spheres(), BVHBuilder::Sah(SahOptions::DEFAULT), None);
+
+    let (median_stats, sah_stats) = (BVHStats::of(&median, 1.0), BVHStats::of(&sah, 1.0));
+    assert_eq!(sah.objects(), 208);
+    assert_eq!(sah_stats.leaf_objects, 208);
+    assert!(sah_stats.sah_cost() < median_stats.sah_cost());
+
+    let ray_t = Interval {min: 0.001, max: f64::INFINITY};
+    for k in 0..50 {
+        let ray = Ray {origin: Vector3(-200.0 + 8.0 * k as f64, 30.0, 50.0), dir: Vector3(200.0 - 8.0 * k as f64, -30.0, -50.0), time: 0.0};
+        let (a, b) = (median.hit(&ray, &ray_t), sah.hit(&ray, &ray_t));
+        assert_eq!(a.map(|hit| hit.t), b.map(|hit| hit.t));
+    }
+}
+
+#[test]
+fn linear_bvh_matches_tree() {
+    let material = Lambertian::from_const_col(Color::WHITE).to_dyn();
+    let objects = || sphere_field(&material);
+
+    let builder = BVHBuilder::Sah(SahOptions::DEFAULT);
+    let tree = BVHNode::with_builder(objects(), builder, None);
+    let linear = LinearBVH::new(objects(), builder);
+    assert_eq!(linear.objects(), 501);
+
+    // BVHNode has an extra node on top for the plane, but nothing else should differ
+    let (tree_stats, linear_stats) = (BVHStats::of(&tree, 1.0), BVHStats::of(&linear, 1.0));
+    assert_eq!(linear_stats.nodes, tree_stats.nodes - 1);
+    assert_eq!(linear_stats.leaves, tree_stats.leaves);
+    assert_eq!(linear_stats.leaf_objects, 500);
+    assert!((linear_stats.sah_cost() - tree_stats.sah_cost()).abs() < 1e-3 * tree_stats.sah_cost());
+
+    let ray_t = Interval {min: 0.001, max: f64::INFINITY};
+    for k in 0..400 {
+        let angle = k as f64 * 0.37;
+        let origin = Vector3(30.0 * angle.cos(), 4.0 + (k % 9) as f64, 30.0 * angle.sin());
+        let target = Vector3((k % 21) as f64 - 10.0, (k % 7) as f64 - 3.0, (k % 15) as f64 - 7.0);
+        let ray = Ray {origin, dir: target - origin, time: 0.0};
+        let (a, b) = (tree.hit(&ray, &ray_t), linear.hit(&ray, &ray_t));
+        assert_eq!(a.map(|hit| (hit.t, hit.p)), b.map(|hit| (hit.t, hit.p)));
+    }
+}
+
+#[test]
+fn lopsided_linear_bvh_fits_its_stack() {
+    // Every sphere is further out than all the ones before it put together, so SAH peels them off one at a time
+    let material = Lambertian::from_const_col(Color::WHITE).to_dyn();
+    let mut list = HittableList::new();
+    let mut objects = Vec::new();
+    for k in 0..100 {
+        let x = -20_f64.powi(k);
+        list.add(Sphere::from_const_pos(x, 0.0, 0.0, 0.4, material.clone()).as_box());
+        objects.push(Sphere::from_const_pos(x, 0.0, 0.0, 0.4, material.clone()).as_box());
+    }
+    let linear = LinearBVH::new(objects, BVHBuilder::Sah(SahOptions::DEFAULT));
+    assert!(BVHStats::of(&linear, 1.0).max_depth <= 64);
+
+    let ray_t = Interval {min: 0.001, max: f64::INFINITY};
+    for k in 0..100 {
+        let ray = Ray {origin: Vector3(-20_f64.powi(k), 0.1, 5.0), dir: Vector3(0.0, 0.0, -1.0), time: 0.0};
+        assert_eq!(linear.hit(&ray, &ray_t).map(|hit| hit.t), list.hit(&ray, &ray_t).map(|hit| hit.t));
+    }
+}
+
+#[test]
+fn parallel_bvh_matches_serial() {
+    let material = Lambertian::from_const_col(Color::WHITE).to_dyn();
+    let objects = || {
+        let mut objects = Vec::new();
+        for k in 0..12000 {
+            let (x, y, z) = ((k * 37 % 101) as f64, (k * 53 % 89) as f64, (k * 71 % 97) as f64);
+            objects.push(Sphere::from_const_pos(x, y * 0.5, z, 0.3 + (k % 5) as f64 * 0.1, material.clone()).as_box());
+        }
+        objects
+    };
+
+    let threadpool = ThreadPool::new(4);
+    let builder = BVHBuilder::Sah(SahOptions::DEFAULT);
+    let serial = BVHNode::with_builder(objects(), builder, None);
+    let linear_serial = LinearBVH::new(objects(), builder);
+    let linear_parallel = LinearBVH::new_parallel(objects(), builder, &threadpool);
+    let bvh4_serial = Bvh4::new(objects(), builder);
+    let bvh4_parallel = Bvh4::new_parallel(objects(), builder, &threadpool);
+
+    for (a, b) in [(BVHStats::of(&linear_serial, 1.0), BVHStats::of(&linear_parallel, 1.0)), (BVHStats::of(&bvh4_serial, 1.0), BVHStats::of(&bvh4_parallel, 1.0))] {
+        assert_eq!((a.nodes, a.leaves, a.max_depth, a.leaf_objects), (b.nodes, b.leaves, b.max_depth, b.leaf_objects));
+        assert_eq!((a.interior_area, a.leaf_area, a.root_area), (b.interior_area, b.leaf_area, b.root_area));
+        assert!(b.build_time > Duration::ZERO);
+    }
+
+    let ray_t = Interval {min: 0.001, max: f64::INFINITY};
+    for k in 0..200 {
+        let origin = Vector3(-20.0, 30.0 + (k % 13) as f64, -20.0);
+        let dir = Vector3(20.0 + (k % 17) as f64 * 7.0, -20.0, 20.0 + (k % 11) as f64 * 10.0);
+        let ray = Ray {origin, dir, time: 0.0};
+        let hits = [serial.hit(&ray, &ray_t), linear_parallel.hit(&ray, &ray_t), bvh4_parallel.hit(&ray, &ray_t)].map(|hit| hit.map(|hit| hit.t));
+        assert_eq!(hits[0], hits[1]);
+        assert_eq!(hits[0], hits[2]);
+    }
+}
+
+#[test]
+fn refit_follows_moving_spheres() {
+    let spline = LinearSpline::new(vec![Vector3(0.0, 0.0, 0.0), Vector3(2.0, 4.0, 0.0), Vector3(4.0, 0.0, 0.0)]);
+    let bbox = spline.bound_interval(Interval {min: 0.5, max: 1.5});
+    assert_eq!((bbox.x.min, bbox.x.max, bbox.y.min, bbox.y.max), (1.0, 3.0, 2.0, 4.0));
+    assert_eq!(spline.sample(2.0), Vector3(4.0, 0.0, 0.0));
+
+    // A grid of spheres that have all swapped places by time 1
+    let material = Lambertian::from_const_col(Color::WHITE).to_dyn();
+    let grid = |k: usize| Vector3((k % 4) as f64, (k / 4 % 4) as f64, (k / 16) as f64) * 3.0;
+    let mut shuffled: Vec<usize> = (0..64).collect();
+    shuffled.sort_by_key(|&k| k * 2654435761 % 4093);
+    let spheres = || {
+        let mut list = HittableList::new();
+        for k in 0..64 {
+            let (start, end) = (grid(k), grid(shuffled[k]));
+            list.add(Sphere::new(Animation::linear(vec![start, end], 0.3), 0.3, material.clone()).as_box());
+        }
+        // Built around where they start, so the first shutter leaves the trees as they are
+        list.refit_bounds(&Interval {min: 0.0, max: 0.05});
+        list
+    };
+    let builder = BVHBuilder::Sah(SahOptions::DEFAULT);
+    let mut tree = BVHNode::with_builder(spheres().objects(), builder, None);
+    let mut linear = LinearBVH::new(spheres().objects(), builder);
+    let mut bvh4 = Bvh4::new(spheres().objects(), builder);
+    let mut parallel = Bvh4::new_parallel(spheres().objects(), builder, &ThreadPool::new(4));
+    let mut reference = spheres();
+
+    let ray_t = Interval {min: 0.001, max: f64::INFINITY};
+    let build_times = |trees: [&dyn Hittable; 3]| trees.map(|tree| BVHStats::of(tree, 1.0).build_time);
+    let built = build_times([&tree, &linear, &bvh4]);
+    for shutter in [Interval {min: 0.0, max: 0.05}, Interval {min: 0.95, max: 1.0}] {
+        tree.refit(&shutter);
+        linear.refit(&shutter);
+        bvh4.refit(&shutter);
+        parallel.refit(&shutter);
+        if shutter.min == 0.0 {
+            assert_eq!(build_times([&tree, &linear, &bvh4]), built);
+        }
+        reference.refit_bounds(&shutter);
+        assert_eq!(tree.bounding().x.max, reference.bounding().x.max);
+        assert!((linear.bounding().x.max - reference.bounding().x.max).abs() < 1e-5);
+        assert_eq!(bvh4.bounding().x.max, reference.bounding().x.max);
+
+        for k in 0..300 {
+            let time = shutter.min + shutter.size() * (k % 10) as f64 / 10.0;
+            let origin = Vector3(-20.0, 30.0 + (k % 7) as f64, -20.0);
+            let target = Vector3((k * 13 % 10) as f64, (k * 7 % 10) as f64, (k * 3 % 10) as f64);
+            let ray = Ray {origin, dir: target - origin, time};
+            let expected = reference.hit(&ray, &ray_t).map(|hit| hit.t);
+            assert_eq!(tree.hit(&ray, &ray_t).map(|hit| hit.t), expected);
+            assert_eq!(linear.hit(&ray, &ray_t).map(|hit| hit.t), expected);
+            assert_eq!(bvh4.hit(&ray, &ray_t).map(|hit| hit.t), expected);
+        }
+    }
+
+    // The spheres moved too far for the old trees, so they should all have been rebuilt into what a fresh build gives
+    let moved = || {
+        let mut moved = spheres();
+        moved.refit_bounds(&Interval {min: 0.95, max: 1.0});
+        moved.objects()
+    };
+    let fresh = LinearBVH::new(moved(), builder);
+    let (a, b) = (BVHStats::of(&linear, 1.0), BVHStats::of(&fresh, 1.0));
+    assert_eq!((a.nodes, a.interior_area, a.leaf_area), (b.nodes, b.interior_area, b.leaf_area));
+    let (a, b) = (BVHStats::of(&tree, 1.0), BVHStats::of(&fresh, 1.0));
+    assert_eq!((a.nodes, a.leaves, a.leaf_objects), (b.nodes, b.leaves, b.leaf_objects));
+    let fresh = Bvh4::new(moved(), builder);
+    for bvh4 in [&bvh4, &parallel] {
+        let (a, b) = (BVHStats::of(bvh4, 1.0), BVHStats::of(&fresh, 1.0));
+        assert_eq!((a.nodes, a.interior_area, a.leaf_area), (b.nodes, b.interior_area, b.leaf_area));
+    }
+}
+
+#[test]
+fn scene_refits_to_the_shutter() {
+    let material = Lambertian::from_const_col(Color::WHITE).to_dyn();
+    let mut objects = Vec::new();
+    for k in 0..8 {
+        let start = Vector3(0.0, k as f64, 0.0);
+        objects.push(Sphere::new(Animation::linear(vec![start, start + Vector3(10.0, 0.0, 0.0)], 0.5), 0.5, material.clone()).as_box());
+    }
+    let camera = Camera::new(QualityOptions::DEFAULT, 1.0, Vector3(0.0, 0.0, 10.0), 40.0, Vector3(0.0, 0.0, 0.0), Vector3(0.0, 1.0, 0.0), 0.0, 10.0, 1)
+        .with_shutter(Interval {min: 0.0, max: 0.1});
+    let mut scene = Scene::new(camera, Bvh4::new(objects, BVHBuilder::Sah(SahOptions::DEFAULT)));
+    assert!(scene.root.bounding().x.max >= 10.5);
+
+    scene.refit();
+    assert!((scene.root.bounding().x.max - 1.5).abs() < 1e-5);
+}
+
+#[test]
+fn bvh4_matches_linear_bvh() {
+    let material = Lambertian::from_const_col(Color::WHITE).to_dyn();
+    let objects = || sphere_field(&material);
+    let builder = BVHBuilder::Sah(SahOptions::DEFAULT);
+    let linear = LinearBVH::new(objects(), builder);
+    let wide = Bvh4::new(objects(), builder);
+    assert_eq!(wide.objects(), 501);
+
+    // Collapsing keeps the leaves and drops about two thirds of the interior nodes. Leaves are lanes in Bvh4, not nodes
+    let (a, b) = (BVHStats::of(&linear, 1.0), BVHStats::of(&wide, 1.0));
+    assert_eq!((a.leaves, a.leaf_objects), (b.leaves, b.leaf_objects));
+    assert!(b.nodes < (a.nodes - a.leaves) / 2);
+    assert!(b.max_depth < a.max_depth);
+
+    let ray_t = Interval {min: 0.001, max: f64::INFINITY};
+    for k in 0..500 {
+        let angle = k as f64 * 0.29;
+        let origin = Vector3(40.0 * angle.cos(), 2.0 + (k % 11) as f64, 40.0 * angle.sin());
+        let target = Vector3((k % 27) as f64 - 13.0, (k % 9) as f64 - 2.0, (k % 23) as f64 - 11.0);
+        let ray = Ray {origin, dir: target - origin, time: 0.0};
+        let (a, b) = (linear.hit(&ray, &ray_t), wide.hit(&ray, &ray_t));
+        assert_eq!(a.map(|hit| (hit.t, hit.p)), b.map(|hit| (hit.t, hit.p)));
+    }
+}
+
+#[test]
+fn packet_hits_match_scalar() {
+    let a = SimdInterval::<4>::from_lanes(&[Interval {min: 0.0, max: 1.0}; 4]);
+    let b = SimdInterval::<4>::from_lanes(&[Interval {min: 2.0, max: 3.0}; 4]);
+    let mixed = a.replace(b, SimdIntervalMask::from_lanes(&[false, true, false, true]));
+    assert_eq!([0, 1, 2, 3].map(|lane| mixed.lane(lane).min), [0.0, 2.0, 0.0, 2.0]);
+
+    let material = Lambertian::from_const_col(Color::WHITE).to_dyn();
+    let objects = || sphere_field(&material);
+    let builder = BVHBuilder::Sah(SahOptions::DEFAULT);
+    let trees: [Box<dyn Hittable>; 2] = [Box::new(LinearBVH::new(objects(), builder)), Box::new(Bvh4::new(objects(), builder))];
+
+    // A camera's worth of rays fanning out from one point, not a multiple of the packet size
+    let origin = Vector3(0.0, 6.0, -40.0);
+    let fan: Vec<Ray> = (0..1003).map(|k| {
+        let target = Vector3((k % 59) as f64 * 0.5 - 15.0, (k / 59) as f64 * 0.7 - 1.0, 0.0);
+        Ray {origin, dir: target - origin, time: 0.0}
+    }).collect();
+    // A pixel's samples from a defocused camera, which mostly go into boxes all together
+    let pixels: Vec<Vec<Ray>> = (0..40).map(|pixel| (0..37).map(|k| {
+        let origin = Vector3((k % 3) as f64 * 0.01, 6.0 + (k % 5) as f64 * 0.01, -40.0);
+        let target = Vector3((pixel % 8) as f64 * 3.0 - 12.0 + (k % 6) as f64 * 0.002, (pixel / 8) as f64 * 1.5 + (k / 6) as f64 * 0.002, 0.0);
+        Ray {origin, dir: target - origin, time: 0.0}
+    }).collect()).collect();
+    // Straight down and straight along z, so the other axes divide by zero
+    let axes: Vec<Ray> = (0..50).map(|k| {
+        let origin = Vector3((k % 10) as f64 * 3.0 - 15.0, (k / 10) as f64 * 2.0 + 1.0, (k % 7) as f64 * 4.0 - 20.0);
+        Ray {origin, dir: if k % 2 == 0 {Vector3(0.0, -1.0, 0.0)} else {Vector3(0.0, 0.0, 1.0)}, time: 0.0}
+    }).collect();
+
+    let ray_t = Interval {min: 0.001, max: f64::INFINITY};
+    for tree in &trees {
+        let matches_scalar = |rays: &[Ray]| {
+            let hits = tree.hit_all(rays, &ray_t);
+            assert_eq!(hits.len(), rays.len());
+            for (ray, hit) in rays.iter().zip(&hits) {
+                match (tree.hit(ray, &ray_t), hit) {
+                    (None, None) => {},
+                    (Some(a), Some(b)) => {
+                        assert!((a.t - b.t).abs() < 1e-9);
+                        assert!((a.p - b.p).length() < 1e-9);
+                    },
+                    _ => panic!("Packet and scalar hits disagree"),
+                }
+            }
+            hits.iter().filter(|hit| hit.is_some()).count()
+        };
+        assert!(matches_scalar(&fan) > 500);
+        assert!(pixels.iter().map(|rays| matches_scalar(rays)).sum::<usize>() > 1000);
+        assert!(matches_scalar(&axes) > 25);
+        assert!(tree.hit_all(&[], &ray_t).is_empty());
+    }
+}
+
+// A floor under 500 overlapping spheres of a few sizes, for checking the BVHs against each other
+fn sphere_field(material: &Arc<Box<dyn Material>>) -> Vec<Box<dyn Hittable>> {
+    let mut objects = vec![Plane::new(Vector3(0.0, -1.0, 0.0), Vector3(0.0, 1.0, 0.0), material.clone()).as_box()];
+    for k in 0..500 {
+        let (x, y, z) = ((k * 7 % 31) as f64 - 15.0, (k * 5 % 13) as f64, (k * 11 % 29) as f64 - 14.0);
+        objects.push(Sphere::from_const_pos(x, y, z, 0.2 + (k % 4) as f64 * 0.15, material.clone()).as_box());
+    }
+    objects
+}
+
+fn debugger() -> Arc<Mutex<Debugger>> {
+    Arc::new(Mutex::new(Debugger::sink()))
+}
+
+// 4 pi times the mean density over uniformly random directions, which should be 1
+fn integrate_over_sphere(pdf: impl Fn(&Vector3) -> f64) -> f64 {
+    let samples = 200_000;
+    (0..samples).map(|_| pdf(&Vector3::random_unit())).sum::<f64>() * 4.0 * PI / samples as f64
+}
+
+#[test]
+fn light_pdfs_integrate_to_one() {
+    let material = DiffuseLight::from_const_col(Color::WHITE).to_dyn();
+    let origin = Vector3(0.0, 0.0, 0.0);
+    // A fan of faces with very different areas under the origin
+    let mut mesh = Mesh::new();
+    mesh.positions = vec![Vector3(-1.0, -1.0, -1.0), Vector3(1.0, -1.0, -1.0), Vector3(1.0, -1.0, -0.8), Vector3(1.0, -1.0, 1.0), Vector3(-1.0, -1.0, 1.0)];
+    mesh.indices = vec![[0, 1, 2], [0, 2, 3], [0, 3, 4]];
+    let lights: [Box<dyn Hittable>; 3] = [
+        Sphere::from_const_pos(0.0, 2.0, 0.0, 1.0, material.clone()).as_box(),
+        Quad::new(Vector3(-1.0, -1.0, 1.0), Vector3(2.0, 0.0, 0.0), Vector3(0.0, 2.0, 0.0), material.clone()).as_box(),
+        TriangleMesh::new(mesh, material).as_box(),
+    ];
+    for light in &lights {
+        let total = integrate_over_sphere(|dir| light.pdf_value(&origin, dir, 0.0));
+        assert!((total - 1.0).abs() < 0.03, "{}", total);
+        // Everything random picks has to be somewhere pdf_value counts
+        for _ in 0..1000 {
+            assert!(light.pdf_value(&origin, &light.random(&origin, 0.0), 0.0) > 0.0);
+        }
+    }
+}
+
+#[test]
+fn smooth_triangle_pdf_matches_sampling() {
+    let material = DiffuseLight::from_const_col(Color::WHITE).to_dyn();
+    // Shading normals tilted well away from the face
+    let triangle = Triangle::new(Vector3(-1.0, 1.0, -1.0), Vector3(2.0, 1.0, -1.0), Vector3(-1.0, 1.0, 2.0), material)
+        .with_normals([Vector3(1.0, 1.0, 0.0).unit(), Vector3(0.0, 1.0, 1.0).unit(), Vector3(-1.0, 1.0, 0.0).unit()]);
+    let origin = Vector3(0.2, 0.0, 0.1);
+
+    let total = integrate_over_sphere(|dir| triangle.pdf_value(&origin, dir, 0.0));
+    assert!((total - 1.0).abs() < 0.03, "{}", total);
+
+    // Averaging 1/pdf over what random picks gives the solid angle the triangle covers
+    let samples = 100_000;
+    let solid_angle = (0..samples).map(|_| 1.0 / triangle.pdf_value(&origin, &triangle.random(&origin, 0.0), 0.0)).sum::<f64>() / samples as f64;
+    let covered = integrate_over_sphere(|dir| if triangle.pdf_value(&origin, dir, 0.0) > 0.0 {1.0} else {0.0});
+    assert!((solid_angle - covered).abs() < 0.03 * covered, "{} {}", solid_angle, covered);
+}
+
+#[test]
+fn lambertian_sampling_is_cosine_weighted() {
+    let material = Lambertian::from_const_col(Color::from_all(0.5)).to_dyn();
+    let floor = Quad::new(Vector3(-1.0, 0.0, -1.0), Vector3(0.0, 0.0, 2.0), Vector3(2.0, 0.0, 0.0), material);
+    let r_in = Ray {origin: Vector3(0.0, 1.0, 0.0), dir: Vector3(0.2, -1.0, 0.1), time: 0.0};
+    let hit = floor.hit(&r_in, &Interval {min: 0.001, max: f64::INFINITY}).unwrap();
+
+    let probe = |dir: &Vector3| Ray {origin: hit.p, dir: *dir, time: 0.0};
+    let total = integrate_over_sphere(|dir| hit.material.pdf(&r_in, &hit, &probe(dir)));
+    assert!((total - 1.0).abs() < 0.01, "{}", total);
+
+    // Cosine-distributed directions average 2/3 up the normal
+    let samples = 100_000;
+    let mut cosine = 0.0;
+    for _ in 0..samples {
+        let rec = hit.material.scatter(&r_in, &hit, debugger()).unwrap();
+        assert!(!rec.specular);
+        assert!((rec.pdf - hit.material.pdf(&r_in, &hit, &rec.scattered)).abs() < 1e-12);
+        // The throughput is what eval over pdf would give
+        let throughput = hit.material.eval(&r_in, &hit, &rec.scattered, debugger()) * (1.0 / rec.pdf);
+        assert!((throughput.r - rec.attenuation.r).abs() < 1e-9);
+        cosine += hit.normal.dot(&rec.scattered.dir.unit());
+    }
+    assert!((cosine / samples as f64 - 2.0 / 3.0).abs() < 0.01);
+}
+
+#[test]
+fn light_sampling_matches_bsdf_sampling() {
+    // A grey floor under a square light, lit only by it
+    let floor = Lambertian::from_const_col(Color::from_all(0.5)).to_dyn();
+    let emit = DiffuseLight::from_const_col(Color::from_all(4.0)).to_dyn();
+    let light = || Quad::new(Vector3(-0.5, 1.0, -0.5), Vector3(1.0, 0.0, 0.0), Vector3(0.0, 0.0, 1.0), emit.clone()).as_box();
+    let mut world = HittableList::new();
+    world.add(Quad::new(Vector3(-5.0, 0.0, -5.0), Vector3(10.0, 0.0, 0.0), Vector3(0.0, 0.0, 10.0), floor).as_box());
+    world.add(light());
+    let mut lights = HittableList::new();
+    lights.add(light());
+    let background = Background::Solid(Color::BLACK);
+    let ray_t = Interval {min: 0.001, max: f64::INFINITY};
+
+    let r_in = Ray {origin: Vector3(0.3, 0.5, 0.2), dir: Vector3(0.1, -1.0, 0.0), time: 0.0};
+    let hit = world.hit(&r_in, &ray_t).unwrap();
+    let emitted = |ray: &Ray| world.hit(ray, &ray_t)
+        .map_or(Color::BLACK, |hit| hit.material.emitted(hit.u, hit.v, &hit.p, debugger()));
+
+    let samples = 100_000;
+    let mut light_only = 0.0;
+    let mut bsdf_only = 0.0;
+    for _ in 0..samples {
+        let shadow = Ray {origin: hit.p, dir: lights.random(&hit.p, 0.0), time: 0.0};
+        let pdf = lights.pdf_value(&shadow.origin, &shadow.dir, 0.0);
+        light_only += (hit.material.eval(&r_in, &hit, &shadow, debugger()) * emitted(&shadow)).r / pdf;
+
+        let rec = hit.material.scatter(&r_in, &hit, debugger()).unwrap();
+        bsdf_only += (rec.attenuation * emitted(&rec.scattered)).r;
+    }
+    let (light_only, bsdf_only) = (light_only / samples as f64, bsdf_only / samples as f64);
+    assert!(light_only > 0.1);
+    assert!((light_only - bsdf_only).abs() < 0.03 * light_only, "{} {}", light_only, bsdf_only);
+
+    // Whole paths, weighing both with MIS, against paths that can only find the light by bouncing into it
+    let mis = (0..samples).map(|_| r_in.color(2, &world, &lights, &background, debugger()).r).sum::<f64>() / samples as f64;
+    let unweighted = (0..samples).map(|_| r_in.color(2, &world, &HittableList::new(), &background, debugger()).r).sum::<f64>() / samples as f64;
+    assert!((mis - unweighted).abs() < 0.03 * mis, "{} {}", mis, unweighted);
+    assert!((mis - light_only).abs() < 0.03 * mis, "{} {}", mis, light_only);
+}
@@ -1,9 +1,14 @@
 use std::sync::{Arc, Mutex};
 
-use crate::{color::Color, image::{self, ImgData}, vector::Point3, writer::Debugger};
+use crate::{color::Color, hit::Hit, image::{self, ImgData}, vector::Point3, writer::Debugger};
 
 pub trait Texture: Send + Sync {
     fn value(&self, u: f64, v: f64, p: &Point3, debugger: Arc<Mutex<Debugger>>) -> Color;
+
+    // For textures that need more of the hit than where it is
+    fn value_at(&self, hit: &Hit, debugger: Arc<Mutex<Debugger>>) -> Color {
+        self.value(hit.u, hit.v, &hit.p, debugger)
+    }
 }
 
 pub struct SolidTexture {
@@ -83,6 +88,10 @@ impl ImageTexture {
         }
     }
 
+    pub fn from_img(image: ImgData) -> Self {
+        Self { image }
+    }
+
     pub fn to_box(self) -> Box<dyn Texture> {
         Box::new(self)
     }
@@ -96,8 +105,27 @@ impl Texture for ImageTexture {
 
         let v = 1.0 - v;
 
-        let i = (u * self.image.width() as f64) as usize;
-        let j = (v * self.image.width() as f64) as usize;
+        let i = ((u * self.image.width() as f64) as usize).min(self.image.width() - 1);
+        let j = ((v * self.image.height() as f64) as usize).min(self.image.height() - 1);
         self.image.pixel(i, j, debugger)
     }
-}
\ No newline at end of file
+}
+
+// The color a mesh blended from its vertex colors at the hit. Anything without them comes out white
+pub struct VertexColorTexture;
+
+impl VertexColorTexture {
+    pub fn to_box(self) -> Box<dyn Texture> {
+        Box::new(self)
+    }
+}
+
+impl Texture for VertexColorTexture {
+    fn value(&self, _u: f64, _v: f64, _p: &Point3, _debugger: Arc<Mutex<Debugger>>) -> Color {
+        Color::WHITE
+    }
+
+    fn value_at(&self, hit: &Hit, _debugger: Arc<Mutex<Debugger>>) -> Color {
+        hit.color.unwrap_or(Color::WHITE)
+    }
+}
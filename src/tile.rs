@@ -0,0 +1,167 @@
+use std::{collections::VecDeque, sync::{Arc, Mutex}};
+
+use threadpool::ThreadPool;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Tile {
+    pub x0: usize,
+    pub y0: usize,
+    pub x1: usize,
+    pub y1: usize,
+}
+
+impl Tile {
+    pub fn pixels(&self) -> impl Iterator<Item = (usize, usize)> {
+        let (x0, x1) = (self.x0, self.x1);
+        (self.y0..self.y1).flat_map(move |j| (x0..x1).map(move |i| (i, j)))
+    }
+
+    pub fn area(&self) -> usize {
+        (self.x1 - self.x0) * (self.y1 - self.y0)
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum TileOrder {
+    Scanline,
+    Spiral,
+    Hilbert,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct TileOptions {
+    pub size: usize,
+    pub order: TileOrder,
+}
+
+impl TileOptions {
+    pub const fn new(size: usize, order: TileOrder) -> Self {
+        Self { size, order }
+    }
+
+    pub const DEFAULT: TileOptions = TileOptions {size: 32, order: TileOrder::Spiral};
+}
+
+pub struct TileScheduler {
+    queues: Vec<Mutex<VecDeque<Tile>>>,
+}
+
+impl TileScheduler {
+    pub fn new(img_width: usize, img_height: usize, options: TileOptions, workers: usize) -> Self {
+        let tiles = Self::tiles(img_width, img_height, options);
+        let workers = workers.max(1);
+
+        // Hand out contiguous runs so each worker starts with neighbouring tiles
+        let queues = (0..workers).map(|w| {
+            let start = w * tiles.len() / workers;
+            let end = (w + 1) * tiles.len() / workers;
+            Mutex::new(tiles[start..end].iter().copied().collect())
+        }).collect();
+
+        Self { queues }
+    }
+
+    pub fn tiles(img_width: usize, img_height: usize, options: TileOptions) -> Vec<Tile> {
+        let size = options.size.max(1);
+        let tiles_x = img_width.div_ceil(size);
+        let tiles_y = img_height.div_ceil(size);
+
+        let order = match options.order {
+            TileOrder::Scanline => (0..tiles_y)
+                .flat_map(|y| (0..tiles_x).map(move |x| (x, y)))
+                .collect(),
+            TileOrder::Spiral => spiral(tiles_x, tiles_y),
+            TileOrder::Hilbert => hilbert(tiles_x, tiles_y),
+        };
+
+        order.into_iter().map(|(x, y)| Tile {
+            x0: x * size,
+            y0: y * size,
+            x1: ((x + 1) * size).min(img_width),
+            y1: ((y + 1) * size).min(img_height),
+        }).collect()
+    }
+
+    // Take from the front of our own queue, or steal from the back of someone else's
+    fn next(&self, worker: usize) -> Option<Tile> {
+        if let Some(tile) = self.queues[worker].lock().unwrap().pop_front() {
+            return Some(tile);
+        }
+        let len = self.queues.len();
+        (1..len).find_map(|offset| {
+            self.queues[(worker + offset) % len].lock().unwrap().pop_back()
+        })
+    }
+
+    pub fn run<F>(self, threadpool: &ThreadPool, work: F)
+        where F: Fn(Tile) + Send + Sync + 'static {
+        let this = Arc::new(self);
+        let work = Arc::new(work);
+        for worker in 0..(this.queues.len()) {
+            let this = this.clone();
+            let work = work.clone();
+            threadpool.execute(move || {
+                while let Some(tile) = this.next(worker) {
+                    work(tile);
+                }
+            });
+        }
+        threadpool.join();
+        assert_eq!(threadpool.panic_count(), 0);
+    }
+}
+
+fn spiral(tiles_x: usize, tiles_y: usize) -> Vec<(usize, usize)> {
+    let total = tiles_x * tiles_y;
+    let mut order = Vec::with_capacity(total);
+    let (mut x, mut y) = (((tiles_x as isize) - 1) / 2, ((tiles_y as isize) - 1) / 2);
+    let dirs = [(1, 0), (0, 1), (-1, 0), (0, -1)];
+    let mut step = 1;
+    let mut dir = 0;
+
+    while order.len() < total {
+        // Each step length is walked twice before growing: right, down, left, up, ...
+        for _ in 0..2 {
+            for _ in 0..step {
+                if x >= 0 && y >= 0 && (x as usize) < tiles_x && (y as usize) < tiles_y {
+                    order.push((x as usize, y as usize));
+                }
+                x += dirs[dir].0;
+                y += dirs[dir].1;
+            }
+            dir = (dir + 1) % 4;
+        }
+        step += 1;
+    }
+    order
+}
+
+fn hilbert(tiles_x: usize, tiles_y: usize) -> Vec<(usize, usize)> {
+    let n = tiles_x.max(tiles_y).max(1).next_power_of_two();
+    (0..n*n)
+        .map(|d| hilbert_d2xy(n, d))
+        .filter(|&(x, y)| x < tiles_x && y < tiles_y)
+        .collect()
+}
+
+fn hilbert_d2xy(n: usize, d: usize) -> (usize, usize) {
+    let (mut x, mut y) = (0, 0);
+    let mut t = d;
+    let mut s = 1;
+    while s < n {
+        let rx = 1 & (t / 2);
+        let ry = 1 & (t ^ rx);
+        if ry == 0 {
+            if rx == 1 {
+                x = s - 1 - x;
+                y = s - 1 - y;
+            }
+            std::mem::swap(&mut x, &mut y);
+        }
+        x += s * rx;
+        y += s * ry;
+        t /= 4;
+        s *= 2;
+    }
+    (x, y)
+}
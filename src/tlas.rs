@@ -0,0 +1,146 @@
+use crate::{aabb::AABB, hit::{Hit, Hittable}, interval::Interval, matrix::Mat4, ray::Ray, transform::Transformed};
+
+const MAX_LEAF: usize = 2;
+
+// Same layout as the mesh BVH: leaves cover `count` entries of `order` from `start`, interior nodes
+// have a count of 0, their left child right after them and their right child at `start`
+struct TlasNode {
+    bbox: AABB,
+    start: u32,
+    count: u32,
+}
+
+// Top level tree over instances. Each instance shares its bottom level tree through an Arc,
+// so moving one only touches the instance and this tree
+pub struct Tlas {
+    instances: Vec<Transformed>,
+    // Instance indices in leaf order, so the instances themselves never move
+    order: Vec<u32>,
+    nodes: Vec<TlasNode>,
+}
+
+impl Tlas {
+    pub fn new(instances: Vec<Transformed>) -> Self {
+        let mut tlas = Tlas {
+            order: (0..instances.len() as u32).collect(),
+            instances,
+            nodes: Vec::new(),
+        };
+        tlas.rebuild();
+        tlas
+    }
+
+    pub fn instance(&self, index: usize) -> &Transformed {
+        &self.instances[index]
+    }
+
+    pub fn num_instances(&self) -> usize {
+        self.instances.len()
+    }
+
+    // Call refit or rebuild afterwards, before rendering
+    pub fn set_transform(&mut self, index: usize, matrix: Mat4) {
+        self.instances[index].set_transform(matrix);
+    }
+
+    // Keeps the tree's shape and only recomputes its boxes. Cheap, but the tree gets worse the further things move
+    pub fn refit(&mut self) {
+        // Children always come after their parent
+        for index in (0..self.nodes.len()).rev() {
+            let node = &self.nodes[index];
+            let start = node.start as usize;
+            let bbox = if node.count == 0 {
+                AABB::enclose(&self.nodes[index + 1].bbox, &self.nodes[start].bbox)
+            } else {
+                self.order[start..(start + node.count as usize)].iter()
+                    .fold(AABB::EMPTY, |bbox, &i| AABB::enclose(&bbox, self.instances[i as usize].bounding()))
+            };
+            self.nodes[index].bbox = bbox;
+        }
+    }
+
+    pub fn rebuild(&mut self) {
+        self.nodes.clear();
+        if !self.instances.is_empty() {
+            build(&self.instances, &mut self.order, 0, &mut self.nodes);
+        }
+    }
+
+    pub fn as_box(self) -> Box<dyn Hittable> {
+        Box::new(self)
+    }
+}
+
+// Median split on the longest axis of the instances' centers
+fn build(instances: &[Transformed], order: &mut [u32], offset: usize, nodes: &mut Vec<TlasNode>) {
+    let bbox = order.iter().fold(AABB::EMPTY, |bbox, &i| AABB::enclose(&bbox, instances[i as usize].bounding()));
+
+    let index = nodes.len();
+    nodes.push(TlasNode {bbox, start: offset as u32, count: order.len() as u32});
+    if order.len() <= MAX_LEAF {
+        return;
+    }
+
+    let centers = order.iter().fold(AABB::EMPTY, |bounds, &i| {
+        let c = instances[i as usize].bounding().center();
+        AABB::enclose(&bounds, &AABB::from(c, c))
+    });
+    let axis = centers.longest_axis();
+    let key = |&i: &u32| {
+        let interval = instances[i as usize].bounding().axis(axis);
+        interval.min + interval.max
+    };
+
+    let mid = order.len() / 2;
+    order.select_nth_unstable_by(mid, |a, b| key(a).partial_cmp(&key(b)).unwrap());
+    let (left, right) = order.split_at_mut(mid);
+
+    build(instances, left, offset, nodes);
+    let right_index = nodes.len();
+    build(instances, right, offset + mid, nodes);
+
+    nodes[index].start = right_index as u32;
+    nodes[index].count = 0;
+}
+
+impl Hittable for Tlas {
+    fn hit(&self, ray: &Ray, ray_t: &Interval) -> Option<Hit> {
+        if self.nodes.is_empty() {return None;}
+
+        let mut closest: Option<Hit> = None;
+        let mut range = *ray_t;
+
+        let mut stack = Vec::with_capacity(64);
+        stack.push(0);
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            if node.bbox.test(ray, &range).is_none() {continue;}
+
+            if node.count == 0 {
+                stack.push(node.start as usize);
+                stack.push(index + 1);
+                continue;
+            }
+
+            let start = node.start as usize;
+            for &i in &self.order[start..(start + node.count as usize)] {
+                if let Some(hit) = self.instances[i as usize].hit(ray, &range) {
+                    range.max = hit.t;
+                    closest = Some(hit);
+                }
+            }
+        }
+        closest
+    }
+
+    fn bounding(&self) -> &AABB {
+        match self.nodes.first() {
+            Some(root) => &root.bbox,
+            None => &AABB::EMPTY,
+        }
+    }
+
+    fn objects(&self) -> usize {
+        self.instances.iter().map(|instance| instance.objects()).sum()
+    }
+}
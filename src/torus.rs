@@ -0,0 +1,93 @@
+use std::{f64::consts::TAU, sync::Arc};
+
+use crate::{aabb::AABB, disk::disk_bounds, hit::{Hit, Hittable}, interval::Interval, material::Material, math::{solve_quadratic, solve_quartic}, onb::Onb, ray::Ray, vector::{Point3, Vector3}};
+
+// A ring of radius major around axis, with a tube of radius minor. Locally the axis is z
+pub struct Torus {
+    center: Point3,
+    frame: Onb,
+    major: f64,
+    minor: f64,
+    material: Arc<Box<dyn Material>>,
+    bbox: AABB,
+}
+
+impl Torus {
+    pub fn new(center: Point3, axis: Vector3, major: f64, minor: f64, material: Arc<Box<dyn Material>>) -> Self {
+        let bbox = disk_bounds(center, axis, major).expand(minor);
+        Torus {
+            center,
+            frame: Onb::new(&axis),
+            major,
+            minor,
+            material,
+            bbox,
+        }
+    }
+
+    pub fn as_box(self) -> Box<dyn Hittable> {
+        Box::new(self)
+    }
+}
+
+impl Hittable for Torus {
+    fn hit(&self, ray: &Ray, ray_t: &Interval) -> Option<Hit> {
+        let o = self.frame.local(&(ray.origin - self.center));
+        let d = self.frame.local(&ray.dir);
+        let (big, small) = (self.major, self.minor);
+
+        // Skip rays that miss the bounding sphere, the quartic is comparatively slow
+        let outer = big + small;
+        if solve_quadratic(d.length_squared(), 2.0 * o.dot(&d), o.length_squared() - outer * outer).is_empty() {
+            return None;
+        }
+
+        // Solve with a unit direction from the point closest to the center, so the coefficients stay small
+        let scale = d.length();
+        let dir = d / scale;
+        let shift = -o.dot(&dir);
+        let start = o + dir * shift;
+
+        // (|p|^2 + R^2 - r^2)^2 = 4 R^2 (x^2 + y^2)
+        let k = start.length_squared() + big * big - small * small;
+        let od = start.dot(&dir);
+        let r4 = 4.0 * big * big;
+        let roots = solve_quartic(
+            1.0,
+            4.0 * od,
+            4.0 * od * od + 2.0 * k - r4 * (dir.x() * dir.x() + dir.y() * dir.y()),
+            4.0 * od * k - 2.0 * r4 * (start.x() * dir.x() + start.y() * dir.y()),
+            k * k - r4 * (start.x() * start.x() + start.y() * start.y()),
+        );
+        let t = roots.into_iter()
+            .map(|root| (root + shift) / scale)
+            .find(|&t| ray_t.surrounds(t))?;
+
+        let p = o + d * t;
+        let sum = p.length_squared();
+        let normal = Vector3(
+            p.x() * (sum - big * big - small * small),
+            p.y() * (sum - big * big - small * small),
+            p.z() * (sum + big * big - small * small),
+        ).unit();
+        let outward_normal = self.frame.transform(&normal);
+        let front_face = ray.dir.dot(&outward_normal) < 0.0;
+
+        // u goes around the ring, v around the tube
+        let ring = (p.x() * p.x() + p.y() * p.y()).sqrt();
+        Some(Hit {
+            p: ray.at(t),
+            normal: if front_face {outward_normal} else {-outward_normal},
+            material: self.material.clone(),
+            t,
+            u: p.y().atan2(p.x()) / TAU + 0.5,
+            v: p.z().atan2(ring - big) / TAU + 0.5,
+            front_face,
+            color: None,
+        })
+    }
+
+    fn bounding(&self) -> &AABB {
+        &self.bbox
+    }
+}
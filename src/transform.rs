@@ -0,0 +1,99 @@
+use std::sync::Arc;
+
+use crate::{aabb::AABB, hit::{Hit, Hittable}, interval::Interval, matrix::Mat4, ray::Ray, vector::{Point3, Vector3}};
+
+// Places a shared child in the world. Rays go into object space, normals come back out
+pub struct Transformed {
+    child: Arc<Box<dyn Hittable>>,
+    matrix: Mat4,
+    inverse: Mat4,
+    // Normals transform by the inverse transpose
+    normal_matrix: Mat4,
+    determinant: f64,
+    bbox: AABB,
+}
+
+impl Transformed {
+    pub fn new(child: Arc<Box<dyn Hittable>>, matrix: Mat4) -> Self {
+        let inverse = matrix.inverse();
+        let bbox = transform_bounds(child.bounding(), &matrix);
+        Transformed {
+            child,
+            matrix,
+            inverse,
+            normal_matrix: inverse.transpose(),
+            determinant: matrix.determinant3().abs(),
+            bbox,
+        }
+    }
+
+    pub fn set_transform(&mut self, matrix: Mat4) {
+        *self = Self::new(self.child.clone(), matrix);
+    }
+
+    pub fn matrix(&self) -> &Mat4 {
+        &self.matrix
+    }
+
+    pub fn as_box(self) -> Box<dyn Hittable> {
+        Box::new(self)
+    }
+}
+
+// Encloses all eight transformed corners
+pub fn transform_bounds(bbox: &AABB, matrix: &Mat4) -> AABB {
+    if !bbox.is_bounded() {
+        return AABB::UNIVERSE;
+    }
+    let mut bounds = AABB::EMPTY;
+    for x in [bbox.x.min, bbox.x.max] {
+        for y in [bbox.y.min, bbox.y.max] {
+            for z in [bbox.z.min, bbox.z.max] {
+                let corner = matrix.transform_point(&Vector3(x, y, z));
+                bounds = AABB::enclose(&bounds, &AABB::from(corner, corner));
+            }
+        }
+    }
+    bounds
+}
+
+impl Hittable for Transformed {
+    fn hit(&self, ray: &Ray, ray_t: &Interval) -> Option<Hit> {
+        // The direction isn't renormalised, so t means the same thing in both spaces
+        let local = Ray {
+            origin: self.inverse.transform_point(&ray.origin),
+            dir: self.inverse.transform_vector(&ray.dir),
+            time: ray.time,
+        };
+        let hit = self.child.hit(&local, ray_t)?;
+
+        Some(Hit {
+            p: ray.at(hit.t),
+            normal: self.normal_matrix.transform_vector(&hit.normal).unit(),
+            ..hit
+        })
+    }
+
+    fn bounding(&self) -> &AABB {
+        &self.bbox
+    }
+
+    fn objects(&self) -> usize {
+        self.child.objects()
+    }
+
+    fn pdf_value(&self, origin: &Point3, dir: &Vector3, time: f64) -> f64 {
+        let local_origin = self.inverse.transform_point(origin);
+        let local_dir = self.inverse.transform_vector(dir).unit();
+        let pdf = self.child.pdf_value(&local_origin, &local_dir, time);
+
+        // Directions get squeezed or spread by the linear part; its Jacobian on the sphere is det / |M w|^3
+        let stretch = self.matrix.transform_vector(&local_dir).length();
+        pdf * stretch * stretch * stretch / self.determinant
+    }
+
+    fn random(&self, origin: &Point3, time: f64) -> Vector3 {
+        let local_origin = self.inverse.transform_point(origin);
+        self.matrix.transform_vector(&self.child.random(&local_origin, time))
+    }
+}
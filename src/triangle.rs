@@ -0,0 +1,146 @@
+use std::sync::Arc;
+
+use rand::random;
+
+use crate::{aabb::AABB, hit::{Hit, Hittable}, interval::Interval, material::Material, ray::Ray, vector::{Point3, Vector3}};
+
+pub struct Triangle {
+    vertices: [Point3; 3],
+    normals: Option<[Vector3; 3]>,
+    uvs: [(f64, f64); 3],
+    material: Arc<Box<dyn Material>>,
+    bbox: AABB,
+}
+
+impl Triangle {
+    pub fn new(a: Point3, b: Point3, c: Point3, material: Arc<Box<dyn Material>>) -> Self {
+        let bbox = AABB::enclose(&AABB::from(a, b), &AABB::from(c, c)).pad(1e-4);
+        Triangle {
+            vertices: [a, b, c],
+            normals: None,
+            // Without texture coordinates, u and v are the barycentrics of b and c
+            uvs: [(0.0, 0.0), (1.0, 0.0), (0.0, 1.0)],
+            material,
+            bbox,
+        }
+    }
+
+    pub fn with_normals(mut self, normals: [Vector3; 3]) -> Self {
+        self.normals = Some(normals);
+        self
+    }
+
+    pub fn with_uvs(mut self, uvs: [(f64, f64); 3]) -> Self {
+        self.uvs = uvs;
+        self
+    }
+
+    pub fn as_box(self) -> Box<dyn Hittable> {
+        Box::new(self)
+    }
+
+    fn area(&self) -> f64 {
+        let [a, b, c] = self.vertices;
+        (b - a).cross(&(c - a)).length() / 2.0
+    }
+}
+
+// Möller–Trumbore, returning (t, b1, b2) where b1 and b2 are the barycentrics of the second and third vertex
+pub fn intersect(vertices: &[Point3; 3], ray: &Ray, ray_t: &Interval) -> Option<(f64, f64, f64)> {
+    let [a, b, c] = *vertices;
+    let e1 = b - a;
+    let e2 = c - a;
+    let pvec = ray.dir.cross(&e2);
+    let det = e1.dot(&pvec);
+
+    // Parallel to the plane, scaled so tiny triangles aren't rejected
+    if det.abs() <= 1e-12 * e1.length() * e2.length() * ray.dir.length() {return None;}
+    let inv_det = 1.0 / det;
+
+    let tvec = ray.origin - a;
+    let b1 = tvec.dot(&pvec) * inv_det;
+    if !(0.0..=1.0).contains(&b1) {return None;}
+
+    let qvec = tvec.cross(&e1);
+    let b2 = ray.dir.dot(&qvec) * inv_det;
+    if b2 < 0.0 || b1 + b2 > 1.0 {return None;}
+
+    let t = e2.dot(&qvec) * inv_det;
+    if !ray_t.surrounds(t) {return None;}
+
+    Some((t, b1, b2))
+}
+
+// Fills in a hit from the barycentrics `intersect` found, shared with meshes that store their triangles elsewhere
+pub fn shade(
+    vertices: &[Point3; 3],
+    normals: Option<[Vector3; 3]>,
+    uvs: &[(f64, f64); 3],
+    material: &Arc<Box<dyn Material>>,
+    ray: &Ray,
+    (t, b1, b2): (f64, f64, f64),
+) -> Hit {
+    let b0 = 1.0 - b1 - b2;
+
+    let [a, b, c] = *vertices;
+    let geometric = (b - a).cross(&(c - a)).unit();
+    let front_face = ray.dir.dot(&geometric) < 0.0;
+
+    // Interpolated normals shade, but the geometric one decides which side we're on
+    let outward_normal = match normals {
+        Some([na, nb, nc]) => {
+            let n = (na * b0 + nb * b1 + nc * b2).unit();
+            if n.dot(&geometric) < 0.0 {-n} else {n}
+        },
+        None => geometric,
+    };
+    let normal = if front_face {outward_normal} else {-outward_normal};
+
+    let [(u0, v0), (u1, v1), (u2, v2)] = *uvs;
+
+    Hit {
+        p: ray.at(t),
+        normal,
+        material: material.clone(),
+        t,
+        u: b0 * u0 + b1 * u1 + b2 * u2,
+        v: b0 * v0 + b1 * v1 + b2 * v2,
+        front_face,
+        color: None,
+    }
+}
+
+impl Hittable for Triangle {
+    fn hit(&self, ray: &Ray, ray_t: &Interval) -> Option<Hit> {
+        let found = intersect(&self.vertices, ray, ray_t)?;
+        Some(shade(&self.vertices, self.normals, &self.uvs, &self.material, ray, found))
+    }
+
+    fn bounding(&self) -> &AABB {
+        &self.bbox
+    }
+
+    fn pdf_value(&self, origin: &Point3, dir: &Vector3, time: f64) -> f64 {
+        let ray = Ray {origin: *origin, dir: *dir, time};
+        let Some((t, _, _)) = intersect(&self.vertices, &ray, &Interval {min: 0.001, max: f64::INFINITY}) else {
+            return 0.0;
+        };
+
+        // Area turns into solid angle by the real surface's slant, not the interpolated shading normal's
+        let [a, b, c] = self.vertices;
+        let geometric = (b - a).cross(&(c - a)).unit();
+        let dist_squared = t * t * dir.length_squared();
+        let cosine = (dir.dot(&geometric) / dir.length()).abs();
+        dist_squared / (cosine * self.area())
+    }
+
+    fn random(&self, origin: &Point3, _time: f64) -> Vector3 {
+        let [a, b, c] = self.vertices;
+        let (mut r1, mut r2): (f64, f64) = (random(), random());
+        if r1 + r2 > 1.0 {
+            r1 = 1.0 - r1;
+            r2 = 1.0 - r2;
+        }
+        a + (b - a) * r1 + (c - a) * r2 - *origin
+    }
+}
@@ -0,0 +1,232 @@
+use std::sync::Arc;
+
+use rand::random;
+
+use crate::{aabb::AABB, color::Color, hit::{Hit, Hittable}, interval::Interval, material::{Lambertian, Material}, mesh::Mesh, ray::Ray, texture::VertexColorTexture, triangle::{intersect, shade}, vector::{Point3, Vector3}};
+
+const MAX_LEAF: usize = 4;
+
+// Leaves cover `count` triangles from `start`; interior nodes have a count of 0, their left child
+// right after them and their right child at `start`
+struct MeshNode {
+    bbox: AABB,
+    start: u32,
+    count: u32,
+}
+
+// One Hittable for a whole mesh: shared vertex buffers, u32 indices and a flat BVH over the triangles
+pub struct TriangleMesh {
+    positions: Vec<Point3>,
+    normals: Option<Vec<Vector3>>,
+    uvs: Option<Vec<(f64, f64)>>,
+    colors: Option<Vec<Color>>,
+    indices: Vec<[u32; 3]>,
+    nodes: Vec<MeshNode>,
+    materials: Vec<Arc<Box<dyn Material>>>,
+    face_materials: Vec<u32>,
+    // Running total of the face areas, for picking faces in proportion to their size
+    areas: Vec<f64>,
+}
+
+impl TriangleMesh {
+    pub fn new(mesh: Mesh, material: Arc<Box<dyn Material>>) -> Self {
+        let faces = mesh.num_triangles();
+        Self::with_materials(mesh, vec![material], vec![0; faces])
+    }
+
+    // Diffuse faces colored by the mesh's vertex colors, all sharing one material
+    pub fn from_vertex_colors(mesh: Mesh) -> Self {
+        if mesh.colors.is_none() {
+            panic!("This mesh doesn't have vertex colors!");
+        }
+        Self::new(mesh, Lambertian::new(VertexColorTexture.to_box()).to_dyn())
+    }
+
+    // Face k is made of materials[face_materials[k]]
+    pub fn with_materials(mesh: Mesh, materials: Vec<Arc<Box<dyn Material>>>, face_materials: Vec<usize>) -> Self {
+        if mesh.positions.len() > u32::MAX as usize || materials.len() > u32::MAX as usize {
+            panic!("This mesh has too many vertices for 32-bit indices!");
+        }
+        if face_materials.len() != mesh.indices.len() || face_materials.iter().any(|&material| material >= materials.len()) {
+            panic!("Every face of a mesh needs one of its materials!");
+        }
+
+        let mut order: Vec<u32> = (0..mesh.indices.len() as u32).collect();
+        let mut nodes = Vec::with_capacity(2 * order.len().div_ceil(MAX_LEAF));
+        if !order.is_empty() {
+            build(&mesh.positions, &mesh.indices, &mut order, 0, &mut nodes);
+        }
+
+        let indices: Vec<[u32; 3]> = order.iter()
+            .map(|&face| mesh.indices[face as usize].map(|index| index as u32))
+            .collect();
+        let face_materials = order.iter().map(|&face| face_materials[face as usize] as u32).collect();
+
+        let mut total = 0.0;
+        let areas = indices.iter().map(|face| {
+            let [a, b, c] = face.map(|index| mesh.positions[index as usize]);
+            total += (b - a).cross(&(c - a)).length() / 2.0;
+            total
+        }).collect();
+
+        TriangleMesh {
+            positions: mesh.positions,
+            normals: mesh.normals,
+            uvs: mesh.uvs,
+            colors: mesh.colors,
+            indices,
+            nodes,
+            materials,
+            face_materials,
+            areas,
+        }
+    }
+
+    pub fn as_box(self) -> Box<dyn Hittable> {
+        Box::new(self)
+    }
+
+    pub fn num_triangles(&self) -> usize {
+        self.indices.len()
+    }
+
+    fn vertices(&self, face: usize) -> [Point3; 3] {
+        self.indices[face].map(|index| self.positions[index as usize])
+    }
+
+    fn total_area(&self) -> f64 {
+        self.areas.last().copied().unwrap_or(0.0)
+    }
+
+    // The nearest face along the ray, with what `intersect` found on it
+    fn closest(&self, ray: &Ray, ray_t: &Interval) -> Option<(usize, (f64, f64, f64))> {
+        if self.nodes.is_empty() {return None;}
+
+        let mut closest = None;
+        let mut range = *ray_t;
+
+        let mut stack = Vec::with_capacity(64);
+        stack.push(0);
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            if node.bbox.test(ray, &range).is_none() {continue;}
+
+            if node.count == 0 {
+                stack.push(node.start as usize);
+                stack.push(index + 1);
+                continue;
+            }
+
+            let start = node.start as usize;
+            for face in start..(start + node.count as usize) {
+                if let Some(found) = intersect(&self.vertices(face), ray, &range) {
+                    range.max = found.0;
+                    closest = Some((face, found));
+                }
+            }
+        }
+        closest
+    }
+}
+
+fn triangle_bbox(positions: &[Point3], face: &[usize; 3]) -> AABB {
+    let [a, b, c] = face.map(|index| positions[index]);
+    AABB::enclose(&AABB::from(a, b), &AABB::from(c, c)).pad(1e-4)
+}
+
+fn centroid(positions: &[Point3], face: &[usize; 3]) -> Point3 {
+    let [a, b, c] = face.map(|index| positions[index]);
+    (a + b + c) / 3.0
+}
+
+// Splits at the median centroid on the longest axis, reordering the face ids so every leaf is one contiguous run
+fn build(positions: &[Point3], faces: &[[usize; 3]], order: &mut [u32], offset: usize, nodes: &mut Vec<MeshNode>) {
+    let face = |id: &u32| &faces[*id as usize];
+    let bbox = order.iter().fold(AABB::EMPTY, |bbox, id| AABB::enclose(&bbox, &triangle_bbox(positions, face(id))));
+
+    let index = nodes.len();
+    nodes.push(MeshNode {bbox, start: offset as u32, count: order.len() as u32});
+    if order.len() <= MAX_LEAF {
+        return;
+    }
+
+    let centroids = order.iter().fold(AABB::EMPTY, |bbox, id| {
+        let c = centroid(positions, face(id));
+        AABB::enclose(&bbox, &AABB::from(c, c))
+    });
+    let axis = centroids.longest_axis();
+    let key = |id: &u32| {
+        let c = centroid(positions, face(id));
+        match axis {
+            0 => c.x(),
+            1 => c.y(),
+            _ => c.z(),
+        }
+    };
+
+    let mid = order.len() / 2;
+    order.select_nth_unstable_by(mid, |a, b| key(a).partial_cmp(&key(b)).unwrap());
+    let (left, right) = order.split_at_mut(mid);
+
+    build(positions, faces, left, offset, nodes);
+    let right_index = nodes.len();
+    build(positions, faces, right, offset + mid, nodes);
+
+    nodes[index].start = right_index as u32;
+    nodes[index].count = 0;
+}
+
+impl Hittable for TriangleMesh {
+    fn hit(&self, ray: &Ray, ray_t: &Interval) -> Option<Hit> {
+        let (face, found) = self.closest(ray, ray_t)?;
+        let [a, b, c] = self.indices[face].map(|index| index as usize);
+        let normals = self.normals.as_ref().map(|normals| [normals[a], normals[b], normals[c]]);
+        let uvs = match &self.uvs {
+            Some(uvs) => [uvs[a], uvs[b], uvs[c]],
+            None => [(0.0, 0.0), (1.0, 0.0), (0.0, 1.0)],
+        };
+        let material = &self.materials[self.face_materials[face] as usize];
+        let mut hit = shade(&self.vertices(face), normals, &uvs, material, ray, found);
+        let (_, b1, b2) = found;
+        hit.color = self.colors.as_ref().map(|colors| colors[a] * (1.0 - b1 - b2) + colors[b] * b1 + colors[c] * b2);
+        Some(hit)
+    }
+
+    fn bounding(&self) -> &AABB {
+        match self.nodes.first() {
+            Some(root) => &root.bbox,
+            None => &AABB::EMPTY,
+        }
+    }
+
+    fn objects(&self) -> usize {
+        self.indices.len()
+    }
+
+    fn pdf_value(&self, origin: &Point3, dir: &Vector3, time: f64) -> f64 {
+        let ray = Ray {origin: *origin, dir: *dir, time};
+        let Some((face, (t, _, _))) = self.closest(&ray, &Interval {min: 0.001, max: f64::INFINITY}) else {
+            return 0.0;
+        };
+
+        // Faces are picked in proportion to their area, so points are uniform over the whole mesh
+        let [a, b, c] = self.vertices(face);
+        let geometric = (b - a).cross(&(c - a)).unit();
+        let dist_squared = t * t * dir.length_squared();
+        let cosine = (dir.dot(&geometric) / dir.length()).abs();
+        dist_squared / (cosine * self.total_area())
+    }
+
+    fn random(&self, origin: &Point3, _time: f64) -> Vector3 {
+        let target = random::<f64>() * self.total_area();
+        let face = self.areas.partition_point(|&area| area < target).min(self.indices.len() - 1);
+
+        let [a, b, c] = self.vertices(face);
+        let (mut r1, mut r2): (f64, f64) = (random(), random());
+        if r1 + r2 > 1.0 {
+            r1 = 1.0 - r1;
+            r2 = 1.0 - r2;
+        }
+        a + (b - a) * r1 + (c - a) * r2 - *origin
+    }
+}
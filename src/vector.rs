@@ -1,12 +1,13 @@
-use std::{ops::{Add, AddAssign, Div, DivAssign, Mul, MulAssign, Neg, Sub}, simd::{LaneCount, Mask, Simd, SupportedLaneCount}};
+use std::{f64::consts::TAU, ops::{Add, AddAssign, Div, DivAssign, Mul, MulAssign, Neg, Sub}, simd::{LaneCount, Mask, Simd, SupportedLaneCount}};
 
 use rand::random;
 
-use crate::{color::Color, simd::Maskish};
+use crate::{color::Color, simd::{Maskish, Simdish}};
 
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub struct Vector3(pub f64, pub f64, pub f64);
 
+#[derive(Clone, Copy)]
 pub struct SimdVector3<const N: usize> (pub Simd<f64, N>, pub Simd<f64, N>, pub Simd<f64, N>) where LaneCount<N>: SupportedLaneCount;
 
 impl<const N: usize> SimdVector3<N>
@@ -104,6 +105,15 @@ impl Vector3 {
         }
     }
 
+    // Around +z, with density cos(theta) / pi
+    pub fn random_cosine_direction() -> Self {
+        let r1: f64 = random();
+        let r2: f64 = random();
+        let phi = TAU * r1;
+        let r = r2.sqrt();
+        Vector3(phi.cos() * r, phi.sin() * r, (1.0 - r2).sqrt())
+    }
+
     pub fn random_on(normal: &Vector3) -> Self {
         let on_sphere = Self::random_unit();
         if on_sphere.dot(normal) > 0.0 {
@@ -153,7 +163,43 @@ impl<const N: usize> Maskish for SimdVector3Mask<N>
     where LaneCount<N>: SupportedLaneCount {
     
     fn replace(self, replace: Self, mask: Self) -> Self {
-        todo!()
+        SimdVector3Mask(
+            self.0.replace(replace.0, mask.0),
+            self.1.replace(replace.1, mask.1),
+            self.2.replace(replace.2, mask.2),
+        )
+    }
+
+    fn lane(&self, index: usize) -> bool {
+        self.0.test(index) && self.1.test(index) && self.2.test(index)
+    }
+
+    fn from_lanes(lanes: &[bool]) -> Self {
+        let mask = Mask::from_lanes(lanes);
+        SimdVector3Mask(mask, mask, mask)
+    }
+}
+
+impl<const N: usize> Simdish for SimdVector3<N>
+    where LaneCount<N>: SupportedLaneCount {
+    type Unpacked = Vector3;
+    type Mask = SimdVector3Mask<N>;
+    const LANES: usize = N;
+
+    fn replace(self, replace: Self, mask: <Self as Simdish>::Mask) -> Self {
+        SimdVector3(
+            mask.0.select(replace.0, self.0),
+            mask.1.select(replace.1, self.1),
+            mask.2.select(replace.2, self.2),
+        )
+    }
+
+    fn lane(&self, index: usize) -> Self::Unpacked {
+        Vector3(self.0[index], self.1[index], self.2[index])
+    }
+
+    fn from_lanes(lanes: &[Self::Unpacked]) -> Self {
+        Self::from_array(std::array::from_fn(|index| lanes[index]))
     }
 }
 
@@ -1,46 +1,73 @@
-use std::{fs::File, io::{BufWriter, Write}, sync::{Arc, Mutex}};
+use std::{fs::File, io::{self, BufWriter, Write}, path::Path, sync::{Arc, Mutex}};
 
-use crate::color::Pixel;
+use png::BitDepth;
 
-pub struct ImgWriter {
-    writer: BufWriter<File>,
-    done: Vec<Pixel>,
-    img_width: usize,
+use crate::{encoder::{ImageEncoder, P3Encoder, P6Encoder, PgmEncoder}, exr_writer::{ExrCompression, ExrEncoder}, framebuffer::Layer, hdr_writer::HdrEncoder, pfm_writer::PfmEncoder, png_writer::PngEncoder};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OutputFormat {
+    PpmAscii,
+    Ppm,
+    Pgm,
+    Png8,
+    Png16,
+    Hdr,
+    Pfm,
+    Exr(ExrCompression),
 }
 
-impl ImgWriter {
-    pub fn new(mut writer: BufWriter<File>, img_width: usize, img_height: usize) -> Self {
-        writer.write_all(
-            format!("P3\n{} {}\n255\n", img_width, img_height).as_bytes()
-        ).unwrap();
-        ImgWriter { writer, done: Vec::new(), img_width }
+impl OutputFormat {
+    pub fn from_path(path: &str) -> Self {
+        match Path::new(path).extension().and_then(|ext| ext.to_str()) {
+            // Binary P6. The text P3 format is still there as OutputFormat::PpmAscii, but only through OUTPUT_FORMAT
+            Some("ppm") => OutputFormat::Ppm,
+            Some("pgm") => OutputFormat::Pgm,
+            Some("png") => OutputFormat::Png8,
+            Some("hdr") => OutputFormat::Hdr,
+            Some("pfm") => OutputFormat::Pfm,
+            Some("exr") => OutputFormat::Exr(ExrCompression::Zip),
+            _ => panic!("Can't tell the output format of {}!", path),
+        }
     }
 
-    pub fn write(&mut self, pixel: Pixel) {
-        self.done.push(pixel);
+    pub fn encoder(&self) -> Box<dyn ImageEncoder> {
+        match *self {
+            OutputFormat::PpmAscii => Box::new(P3Encoder),
+            OutputFormat::Ppm => Box::new(P6Encoder),
+            OutputFormat::Pgm => Box::new(PgmEncoder::new("depth")),
+            OutputFormat::Png8 => Box::new(PngEncoder {depth: BitDepth::Eight}),
+            OutputFormat::Png16 => Box::new(PngEncoder {depth: BitDepth::Sixteen}),
+            OutputFormat::Hdr => Box::new(HdrEncoder),
+            OutputFormat::Pfm => Box::new(PfmEncoder),
+            OutputFormat::Exr(compression) => Box::new(ExrEncoder {compression}),
+        }
     }
+}
 
-    pub fn flush(&mut self) {
-        self.finish();
-        self.writer.flush().unwrap()
+pub struct ImgWriter {
+    writer: BufWriter<File>,
+    encoder: Box<dyn ImageEncoder>,
+}
+
+impl ImgWriter {
+    pub fn new(writer: BufWriter<File>, encoder: Box<dyn ImageEncoder>) -> Self {
+        ImgWriter { writer, encoder }
     }
 
-    fn finish(&mut self) {
-        self.done.sort_by(|a, b| a.index(self.img_width).cmp(&b.index(self.img_width)));
-        for pixel in &self.done {
-            pixel.color.write(&mut self.writer);
-        }
+    pub fn write(&mut self, layers: &[Layer]) {
+        self.encoder.encode(&mut self.writer, layers);
+        self.writer.flush().unwrap()
     }
 }
 
 pub struct DebugWriter {
-    handle: BufWriter<File>,
+    handle: Box<dyn Write + Send>,
 }
 
 impl DebugWriter {
     pub fn new(filename: &str) -> Self {
         Self {
-            handle: BufWriter::new(File::create(filename).unwrap()),
+            handle: Box::new(BufWriter::new(File::create(filename).unwrap())),
         }
     }
 
@@ -67,6 +94,13 @@ impl Debugger {
         }
     }
 
+    // Throws everything away, for when there's no file worth writing, e.g. in tests
+    pub fn sink() -> Self {
+        Self {
+            writer: Arc::new(Mutex::new(DebugWriter {handle: Box::new(io::sink())}))
+        }
+    }
+
     pub fn write<T: ToString>(&mut self, value: T) {
         self.writer.lock().unwrap().write(value);
     }